@@ -1,9 +1,13 @@
-use std::{fs::File, io::Read};
+use std::{fs::File, io::Read, path::Path};
 
-use stakker::{ret, stop, Ret, CX};
+use mj_dom::parser::DocumentChunk;
+use stakker::{fwd, stop, Fwd, CX};
 use stakker_log::info;
 use url::Url;
 
+/// How much of the file is read at a time.
+const CHUNK_SIZE: usize = 16 * 1024;
+
 pub struct MjFileHandler;
 
 impl MjFileHandler {
@@ -11,14 +15,37 @@ impl MjFileHandler {
         Some(Self {})
     }
 
-    pub fn fetch(&mut self, cx: CX![], url: Url, ret: Ret<String>) {
+    pub fn fetch(&mut self, cx: CX![], url: Url, sink: Fwd<DocumentChunk>) {
         info!([cx], "Fetching {}", url);
-        let url = url
+        let path = url
             .to_file_path()
             .expect("Could not convert url to file path");
-        let mut buf = String::new();
-        File::open(url).unwrap().read_to_string(&mut buf).unwrap();
-        ret!([ret], buf);
+        // Files carry no Content-Type, so the extension decides between the HTML and XML parsers
+        // and the encoding is left to sniffing
+        let content_type = content_type_for(&path).map(str::to_string);
+        let mut file = File::open(path).unwrap();
+        let url = Some(url);
+        fwd!([sink], DocumentChunk::Start { content_type, url });
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            match file.read(&mut buf).unwrap() {
+                0 => break,
+                read => fwd!([sink], DocumentChunk::Data(buf[..read].to_vec())),
+            }
+        }
+        fwd!([sink], DocumentChunk::End);
         stop!(cx);
     }
 }
+
+/// The type a file is taken to be from its extension, for the extensions that need the XML parser.
+/// Anything else is parsed as HTML.
+fn content_type_for(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "xhtml" | "xht" => Some("application/xhtml+xml"),
+        "svg" => Some("image/svg+xml"),
+        "xml" => Some("application/xml"),
+        _ => None,
+    }
+}
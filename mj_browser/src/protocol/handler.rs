@@ -1,4 +1,5 @@
-use stakker::{actor_in_slab, call, ActorOwnSlab, Ret, CX};
+use mj_dom::{forms::FormSubmission, parser::DocumentChunk};
+use stakker::{actor_in_slab, call, ActorOwnSlab, Fwd, CX};
 use url::Url;
 
 use super::{file::MjFileHandler, http::MjHttpHandler};
@@ -16,17 +17,34 @@ impl MjProtocolHandler {
         })
     }
 
-    pub fn fetch(&mut self, cx: CX![], url: Url, ret: Ret<String>) {
+    /// Fetch `url`, streaming its body into `sink` a chunk at a time as it arrives.
+    pub fn fetch(&mut self, cx: CX![], url: Url, sink: Fwd<DocumentChunk>) {
         match url.scheme() {
             "file" => {
                 let actor = actor_in_slab!(self.file_slab, cx, MjFileHandler::init());
-                call!([actor], fetch(url, ret))
+                call!([actor], fetch(url, sink))
             }
             "http" | "https" => {
                 let actor = actor_in_slab!(self.http_slab, cx, MjHttpHandler::init());
-                call!([actor], fetch(url, ret))
+                call!([actor], fetch(url, sink))
             }
             _ => unimplemented!(),
         };
     }
+
+    /// Make the request submitting a form, streaming the page that comes back into `sink`. Files
+    /// can't take a body, so posting to one just fetches it.
+    pub fn submit(&mut self, cx: CX![], submission: FormSubmission, sink: Fwd<DocumentChunk>) {
+        match submission {
+            FormSubmission::Post {
+                url,
+                content_type,
+                body,
+            } if matches!(url.scheme(), "http" | "https") => {
+                let actor = actor_in_slab!(self.http_slab, cx, MjHttpHandler::init());
+                call!([actor], post(url, content_type, body, sink))
+            }
+            submission => self.fetch(cx, submission.url().clone(), sink),
+        }
+    }
 }
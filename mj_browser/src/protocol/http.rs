@@ -1,22 +1,93 @@
-use stakker::{ret, stop, Ret, CX};
+use std::io::Read;
+
+use mj_dom::parser::DocumentChunk;
+use stakker::{fwd, fwd_to, stop, Fwd, PipedThread, CX};
 use stakker_log::info;
 use url::Url;
 
-pub struct MjHttpHandler;
+/// How much of the body is read from the connection at a time.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+pub struct MjHttpHandler {
+    download: Option<PipedThread<(), DocumentChunk>>,
+    sink: Option<Fwd<DocumentChunk>>,
+}
 
 impl MjHttpHandler {
     pub fn init(cx: CX![]) -> Option<Self> {
-        Some(Self {})
+        Some(Self {
+            download: None,
+            sink: None,
+        })
     }
 
-    pub fn fetch(&mut self, cx: CX![], url: Url, ret: Ret<String>) {
+    pub fn fetch(&mut self, cx: CX![], url: Url, sink: Fwd<DocumentChunk>) {
         info!([cx], "Fetching {}", url);
-        let buf = ureq::get(url.as_ref())
-            .call()
-            .unwrap()
-            .into_string()
-            .unwrap();
-        ret!([ret], buf);
+        self.download(cx, sink, move || ureq::get(url.as_ref()).call());
+    }
+
+    /// Send `body` to `url` as `content_type`, streaming the response into `sink` like a fetch.
+    pub fn post(
+        &mut self,
+        cx: CX![],
+        url: Url,
+        content_type: String,
+        body: Vec<u8>,
+        sink: Fwd<DocumentChunk>,
+    ) {
+        info!([cx], "Posting to {}", url);
+        self.download(cx, sink, move || {
+            ureq::post(url.as_ref())
+                .set("Content-Type", &content_type)
+                .send_bytes(&body)
+        });
+    }
+
+    fn download(
+        &mut self,
+        cx: CX![],
+        sink: Fwd<DocumentChunk>,
+        request: impl FnOnce() -> Result<ureq::Response, ureq::Error> + Send + 'static,
+    ) {
+        self.sink = Some(sink);
+        // The body is read on its own thread so each chunk reaches the DOM as it arrives
+        self.download = Some(PipedThread::spawn(
+            fwd_to!([cx], received() as (DocumentChunk)),
+            fwd_to!([cx], download_terminated() as (Option<String>)),
+            cx,
+            move |link| {
+                let response = request().unwrap();
+                let content_type = response.header("Content-Type").map(str::to_string);
+                // After any redirects, which is where relative URLs in the page lead from
+                let url = Url::parse(response.get_url()).ok();
+                link.send(DocumentChunk::Start { content_type, url });
+                let mut reader = response.into_reader();
+                let mut buf = vec![0; CHUNK_SIZE];
+                loop {
+                    let read = reader.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    // A failed send means the handler is gone, so nobody wants the rest
+                    if !link.send(DocumentChunk::Data(buf[..read].to_vec())) {
+                        return;
+                    }
+                }
+                link.send(DocumentChunk::End);
+            },
+        ));
+    }
+
+    fn received(&mut self, cx: CX![], chunk: DocumentChunk) {
+        if let Some(sink) = &self.sink {
+            fwd!([sink], chunk);
+        }
+    }
+
+    fn download_terminated(&mut self, cx: CX![], panic: Option<String>) {
+        if let Some(msg) = panic {
+            panic!("Unexpected thread failure: {}", msg);
+        }
         stop!(cx);
     }
 }
@@ -1,7 +1,7 @@
 use crate::protocol::handler::MjProtocolHandler;
-use mj_dom::MjDom;
+use mj_dom::{parser::DocumentChunk, MjDom};
 use mj_layout::MjLayout;
-use stakker::{actor, call, ret_nop, ret_shutdown, ret_some_to, ActorOwn, CX};
+use stakker::{actor, call, fwd_to, ret_nop, ret_shutdown, ActorOwn, CX};
 use url::Url;
 
 pub struct MjWebview {
@@ -16,8 +16,8 @@ impl MjWebview {
         let dom = actor!(cx, MjDom::init(), ret_shutdown!(cx));
         let layout = actor!(cx, MjLayout::init(dom.clone()), ret_shutdown!(cx));
         let protocol_handler = actor!(cx, MjProtocolHandler::init(), ret_nop!());
-        let fetch_ret = ret_some_to!([dom], parse_document() as (String));
-        call!([protocol_handler], fetch(url.clone(), fetch_ret));
+        let sink = fwd_to!([dom], stream_document() as (DocumentChunk));
+        call!([protocol_handler], fetch(url.clone(), sink));
 
         Some(Self {
             dom,
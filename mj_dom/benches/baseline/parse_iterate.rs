@@ -0,0 +1,103 @@
+//! [`parse_iterate`](../parse_iterate.rs) as it runs against 965bfa7, where every node is an actor
+//! of its own and the iterator steps from one to the next by messages. It doesn't build against
+//! the current tree, so Cargo leaves it alone; see the other bench for how to run it.
+//!
+//! The DOM of that revision doesn't say when it has finished loading, so parsing is taken to be
+//! over once the parser thread has gone quiet for a while, and timed up to its last operation.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use mj_dom::{dom_iterator::ForwardDomIterator, nodes::DomEntry, MjDom};
+use mj_utilities::actor_iterator::ActorIterator;
+use stakker::{actor, call, ret_do, ret_nop, ret_some_do, Actor, ActorOwn, Stakker};
+
+const SECTIONS: usize = 2500;
+const QUIET: Duration = Duration::from_millis(200);
+
+fn page(sections: usize) -> String {
+    let mut html = String::from("<!DOCTYPE html><html><head><title>Bench</title></head><body>");
+    for i in 0..sections {
+        html.push_str(&format!(
+            "<div class=section id=s{i}><h2>Section {i}</h2><p>Some <b>bold</b> and \
+             <i>italic</i> text with a <a href=\"#s{i}\">link</a>.</p><ul><li>one</li>\
+             <li>two</li></ul></div>"
+        ));
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn run_until(stakker: &mut Stakker, woken: &AtomicBool, mut done: impl FnMut() -> bool) {
+    loop {
+        stakker.run(Instant::now(), false);
+        if done() {
+            return;
+        }
+        if woken.swap(false, Ordering::SeqCst) {
+            stakker.poll_wake();
+        } else {
+            thread::sleep(Duration::from_micros(50));
+        }
+    }
+}
+
+fn main() {
+    let html = page(SECTIONS);
+    let mut stakker = Stakker::new(Instant::now());
+    let woken = Arc::new(AtomicBool::new(false));
+    let waker = woken.clone();
+    stakker.set_poll_waker(move || waker.store(true, Ordering::SeqCst));
+    let dom = actor!(stakker, MjDom::init(), ret_nop!());
+
+    let start = Instant::now();
+    call!([dom], parse_document(html));
+    let mut last_operation = Instant::now();
+    while last_operation.elapsed() < QUIET {
+        stakker.run(Instant::now(), false);
+        if woken.swap(false, Ordering::SeqCst) {
+            stakker.poll_wake();
+            last_operation = Instant::now();
+        } else {
+            thread::sleep(Duration::from_micros(50));
+        }
+    }
+    println!("parsed in {:?}", last_operation - start);
+
+    let start = Instant::now();
+    let iterator = Rc::new(RefCell::new(None::<ActorOwn<ForwardDomIterator>>));
+    let received = iterator.clone();
+    call!(
+        [dom],
+        iter(ret_some_do!(
+            move |iterator| *received.borrow_mut() = Some(iterator)
+        ))
+    );
+    run_until(&mut stakker, &woken, || iterator.borrow().is_some());
+    let iterator = iterator.borrow_mut().take().unwrap();
+    let mut iterated = 0;
+    loop {
+        let next = Rc::new(RefCell::new(None));
+        let received = next.clone();
+        call!(
+            [iterator],
+            next(ret_do!(move |node: Option<Actor<DomEntry>>| {
+                *received.borrow_mut() = Some(node.is_some())
+            }))
+        );
+        run_until(&mut stakker, &woken, || next.borrow().is_some());
+        if *next.borrow() == Some(false) {
+            break;
+        }
+        iterated += 1;
+    }
+    println!("iterated {} nodes in {:?}", iterated, start.elapsed());
+}
@@ -0,0 +1,63 @@
+//! The runtime the benches drive by hand, waiting on the parser thread without a test harness.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use mj_dom::MjDom;
+use stakker::{actor, call, ret_nop, ret_some_do, ActorOwn, Stakker};
+
+pub struct Runtime {
+    pub stakker: Stakker,
+    woken: Arc<AtomicBool>,
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        let mut stakker = Stakker::new(Instant::now());
+        let woken = Arc::new(AtomicBool::new(false));
+        let waker = woken.clone();
+        stakker.set_poll_waker(move || waker.store(true, Ordering::SeqCst));
+        Self { stakker, woken }
+    }
+
+    pub fn dom(&mut self) -> ActorOwn<MjDom> {
+        actor!(self.stakker, MjDom::init(), ret_nop!())
+    }
+
+    /// Run the runtime until `done` reports true.
+    pub fn run_until(&mut self, mut done: impl FnMut() -> bool) {
+        loop {
+            self.stakker.run(Instant::now(), false);
+            if done() {
+                return;
+            }
+            if self.woken.swap(false, Ordering::SeqCst) {
+                self.stakker.poll_wake();
+            } else {
+                thread::sleep(Duration::from_micros(50));
+            }
+        }
+    }
+
+    /// Parse `html` into `dom` and run until it has loaded, returning how long that took.
+    pub fn load(&mut self, dom: &ActorOwn<MjDom>, html: &str) -> Duration {
+        let start = Instant::now();
+        call!([dom], parse_document(html.as_bytes().to_vec(), None, None));
+        let loaded = Rc::new(RefCell::new(false));
+        let done = loaded.clone();
+        call!(
+            [dom],
+            when_loaded(ret_some_do!(move |()| *done.borrow_mut() = true))
+        );
+        self.run_until(|| *loaded.borrow());
+        start.elapsed()
+    }
+}
@@ -0,0 +1,55 @@
+//! Parses a generated multi-megabyte page a few times over and reports the throughput, from the
+//! bytes going in to the document having loaded.
+
+mod common;
+
+use std::time::Duration;
+
+use common::Runtime;
+
+const ARTICLES: usize = 8000;
+const RUNS: usize = 5;
+
+/// A long page of mostly text, with the attributes, entities, comments and tables a real article
+/// tends to have.
+fn page(articles: usize) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html lang=en><head><meta charset=utf-8><title>Throughput</title>\
+         <link rel=stylesheet href=/style.css></head><body><main>",
+    );
+    for i in 0..articles {
+        html.push_str(&format!(
+            "<article id=a{i} class=\"post entry\" data-index={i}>\
+             <h2><a href=\"/posts/{i}\" title=\"Post {i}\">Post number {i}</a></h2>\
+             <p>Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
+             incididunt ut labore et dolore magna aliqua &amp; ut enim ad minim veniam, quis \
+             nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.</p>\
+             <p>Duis aute irure dolor in <em>reprehenderit</em> in voluptate velit esse cillum \
+             dolore eu fugiat nulla pariatur &lt;{i}&gt;. Excepteur sint occaecat cupidatat non \
+             proident, sunt in culpa qui officia deserunt mollit anim id est laborum.</p>\
+             <!-- article {i} -->\
+             <table><tr><th>Key<th>Value<tr><td>id<td>{i}<tr><td>kind<td>post</table>\
+             <img src=\"/images/{i}.png\" alt=\"Figure {i}\"></article>"
+        ));
+    }
+    html.push_str("</main></body></html>");
+    html
+}
+
+fn main() {
+    let html = page(ARTICLES);
+    let megabytes = html.len() as f64 / 1_000_000.0;
+    let mut runtime = Runtime::new();
+    let dom = runtime.dom();
+
+    let best = (0..RUNS)
+        .map(|_| runtime.load(&dom, &html))
+        .min()
+        .unwrap_or(Duration::MAX);
+    println!(
+        "parsed {:.1}MB in {:?} at best, {:.1}MB/s",
+        megabytes,
+        best,
+        megabytes / best.as_secs_f64()
+    );
+}
@@ -0,0 +1,22 @@
+use std::fmt::{self, Display};
+
+use ecow::EcoString;
+
+/// A parse error reported while building the document, located in the source markup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    pub line: u64,
+    /// Always `None` for now: html5ever and xml5ever only tell the tree builder which line they
+    /// are on, and their errors don't carry a position of their own.
+    pub column: Option<u64>,
+    pub message: EcoString,
+}
+
+impl Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
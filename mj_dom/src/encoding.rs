@@ -0,0 +1,259 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+/// How many bytes the `<meta>` prescan looks at before giving up, and so how much of a streamed
+/// document is buffered before parsing starts.
+pub const PRESCAN_LENGTH: usize = 1024;
+
+/// Pick the encoding to decode a document with, following the WHATWG encoding sniffing
+/// algorithm: a byte order mark wins, then the charset from the transport's `Content-Type`, then a
+/// `<meta>` declaration near the start of the document, and finally windows-1252.
+pub fn sniff_encoding(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type.and_then(charset_from_content_type) {
+        return encoding;
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LENGTH)]) {
+        return encoding;
+    }
+    WINDOWS_1252
+}
+
+/// Pick the encoding to decode an XML document with: a byte order mark wins, then the charset from
+/// the transport's `Content-Type`, then the encoding named in the XML declaration, and otherwise
+/// UTF-8, which XML defaults to.
+pub fn sniff_xml_encoding(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type.and_then(charset_from_content_type) {
+        return encoding;
+    }
+    declared_xml_encoding(bytes).unwrap_or(UTF_8)
+}
+
+/// The `encoding` of an XML declaration such as `<?xml version="1.0" encoding="ISO-8859-1"?>`
+/// at the very start of the document.
+fn declared_xml_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    let end = declaration.windows(2).position(|window| window == b"?>")?;
+    let declaration = &declaration[..end];
+    let name = declaration
+        .windows(8)
+        .position(|window| window == b"encoding")?;
+    let value = declaration[name + 8..]
+        .trim_ascii_start()
+        .strip_prefix(b"=")?
+        .trim_ascii_start();
+    let (&quote, value) = value.split_first()?;
+    if quote != b'"' && quote != b'\'' {
+        return None;
+    }
+    let end = value.iter().position(|&byte| byte == quote)?;
+    Encoding::for_label(&value[..end])
+}
+
+/// The `charset` parameter of a MIME type such as `text/html; charset=utf-8`.
+pub fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        Encoding::for_label(value.as_bytes())
+    })
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// Move `position` past the next `needle`, or to the end when there is none.
+fn skip_past(bytes: &[u8], position: usize, needle: &[u8]) -> usize {
+    bytes[position..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map_or(bytes.len(), |found| position + found + needle.len())
+}
+
+/// The "prescan a byte stream to determine its encoding" algorithm.
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<!--") {
+            position = skip_past(bytes, position + 2, b"-->");
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest
+                .get(5)
+                .is_some_and(|&byte| is_whitespace(byte) || byte == b'/')
+        {
+            position += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut position) {
+                return Some(encoding);
+            }
+        } else if (rest.starts_with(b"<") && rest.get(1).is_some_and(u8::is_ascii_alphabetic))
+            || (rest.starts_with(b"</") && rest.get(2).is_some_and(u8::is_ascii_alphabetic))
+        {
+            while position < bytes.len()
+                && !is_whitespace(bytes[position])
+                && bytes[position] != b'>'
+            {
+                position += 1;
+            }
+            while get_attribute(bytes, &mut position).is_some() {}
+            position += 1;
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position = skip_past(bytes, position, b">");
+        } else {
+            position += 1;
+        }
+    }
+    None
+}
+
+/// Read the attributes of a `<meta>` tag, returning the encoding it declares if any.
+fn meta_encoding(bytes: &[u8], position: &mut usize) -> Option<&'static Encoding> {
+    let mut seen = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = get_attribute(bytes, position) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value.eq_ignore_ascii_case(b"content-type") => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = charset_from_meta_content(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    match need_pragma {
+        None => return None,
+        Some(true) if !got_pragma => return None,
+        _ => {}
+    }
+    match charset? {
+        encoding if encoding == UTF_16BE || encoding == UTF_16LE => Some(UTF_8),
+        encoding if encoding == X_USER_DEFINED => Some(WINDOWS_1252),
+        encoding => Some(encoding),
+    }
+}
+
+/// The "get an attribute" algorithm, returning a lowercased name and its raw value.
+fn get_attribute(bytes: &[u8], position: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while byte_at(bytes, *position).is_some_and(|byte| is_whitespace(byte) || byte == b'/') {
+        *position += 1;
+    }
+    if matches!(byte_at(bytes, *position), None | Some(b'>')) {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    loop {
+        match byte_at(bytes, *position)? {
+            b'=' if !name.is_empty() => break,
+            byte if is_whitespace(byte) => {
+                skip_whitespace(bytes, position);
+                if byte_at(bytes, *position) != Some(b'=') {
+                    return Some((name, Vec::new()));
+                }
+                break;
+            }
+            b'/' | b'>' => return Some((name, Vec::new())),
+            byte => name.push(byte.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+
+    // Step past the `=` and any whitespace before the value
+    *position += 1;
+    skip_whitespace(bytes, position);
+
+    let mut value = Vec::new();
+    match byte_at(bytes, *position)? {
+        quote @ (b'"' | b'\'') => loop {
+            *position += 1;
+            match byte_at(bytes, *position)? {
+                byte if byte == quote => {
+                    *position += 1;
+                    return Some((name, value));
+                }
+                byte => value.push(byte.to_ascii_lowercase()),
+            }
+        },
+        b'>' => Some((name, value)),
+        _ => {
+            while let Some(byte) = byte_at(bytes, *position) {
+                if is_whitespace(byte) || byte == b'>' {
+                    break;
+                }
+                value.push(byte.to_ascii_lowercase());
+                *position += 1;
+            }
+            Some((name, value))
+        }
+    }
+}
+
+fn byte_at(bytes: &[u8], position: usize) -> Option<u8> {
+    bytes.get(position).copied()
+}
+
+fn skip_whitespace(bytes: &[u8], position: &mut usize) {
+    while byte_at(bytes, *position).is_some_and(is_whitespace) {
+        *position += 1;
+    }
+}
+
+/// The "extract a character encoding from a meta element" algorithm, used on the `content`
+/// attribute of `<meta http-equiv="content-type">`.
+fn charset_from_meta_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    loop {
+        let found = content[position..]
+            .windows(7)
+            .position(|window| window.eq_ignore_ascii_case(b"charset"))?;
+        position += found + 7;
+        skip_whitespace(content, &mut position);
+        if byte_at(content, position) == Some(b'=') {
+            break;
+        }
+    }
+
+    position += 1;
+    skip_whitespace(content, &mut position);
+    let value = match byte_at(content, position)? {
+        quote @ (b'"' | b'\'') => {
+            let rest = &content[position + 1..];
+            &rest[..rest.iter().position(|&byte| byte == quote)?]
+        }
+        _ => {
+            let rest = &content[position..];
+            let end = rest
+                .iter()
+                .position(|&byte| is_whitespace(byte) || byte == b';')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        }
+    };
+    Encoding::for_label(value)
+}
@@ -0,0 +1,38 @@
+use std::fmt::{self, Display};
+
+/// Errors raised by DOM operations, named after the DOMException each one corresponds to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomError {
+    /// A name was given that doesn't match the XML `Name` production.
+    InvalidCharacter(String),
+    /// A prefix or namespace combination that isn't allowed, such as a prefix without a namespace.
+    Namespace(String),
+    /// The operation needs a different kind of node, such as parsing markup into a text node.
+    InvalidNodeType,
+    /// A selector or URL that couldn't be parsed.
+    Syntax(String),
+    /// A node was to be put somewhere it can't go, such as inside itself or under a text node.
+    HierarchyRequest,
+    /// The node an edit was relative to isn't where it was expected, such as a child to remove
+    /// that belongs to some other parent.
+    NotFound,
+    /// The node doesn't support the operation, such as attaching a shadow root to an element that
+    /// can't host one.
+    NotSupported,
+}
+
+impl Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCharacter(name) => write!(f, "Invalid character in name {:?}", name),
+            Self::Namespace(name) => write!(f, "Invalid namespace for name {:?}", name),
+            Self::InvalidNodeType => write!(f, "Invalid node type for this operation"),
+            Self::Syntax(value) => write!(f, "Could not parse {:?}", value),
+            Self::HierarchyRequest => write!(f, "The node can't be inserted there"),
+            Self::NotFound => write!(f, "The node is not where it was expected"),
+            Self::NotSupported => write!(f, "The operation is not supported on this node"),
+        }
+    }
+}
+
+impl std::error::Error for DomError {}
@@ -0,0 +1,329 @@
+//! Events dispatched to a node and propagated through its ancestors in capture, target and bubble
+//! phases.
+//!
+//! Listeners are [`Fwd`]s, so most of them run later as messages to some other actor. Dispatch
+//! moves on to the next listener only after a message back to [`MjDom`], which the queue runs
+//! after the listener's message, so that a listener stopping propagation or cancelling the event
+//! is seen before anything else hears about it.
+
+use std::{any::Any, cell::Cell, collections::VecDeque, rc::Rc};
+
+use ecow::EcoString;
+use stakker::{call, fwd, ret, Fwd, Ret, CX};
+
+use crate::{parser::NodeId, MjDom};
+
+/// An event to dispatch, built with the constructor for its kind.
+#[derive(Clone)]
+pub struct Event {
+    /// The name listeners are registered under, such as `click`.
+    pub event_type: EcoString,
+    pub bubbles: bool,
+    pub cancelable: bool,
+    pub detail: EventDetail,
+}
+
+#[derive(Clone)]
+pub enum EventDetail {
+    Mouse(MouseEvent),
+    Keyboard(KeyboardEvent),
+    Focus {
+        /// The node losing focus for a focus event, or gaining it for a blur.
+        related_target: Option<NodeId>,
+    },
+    Input(InputEvent),
+    Custom(Rc<dyn Any>),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub alt: bool,
+    pub ctrl: bool,
+    pub meta: bool,
+    pub shift: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MouseEvent {
+    pub client_x: f64,
+    pub client_y: f64,
+    /// The button that changed, 0 being the main button.
+    pub button: i16,
+    /// The buttons held down, as a bit per button.
+    pub buttons: u16,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyboardEvent {
+    /// The key's value, such as `a` or `Enter`.
+    pub key: EcoString,
+    /// The physical key, such as `KeyA`.
+    pub code: EcoString,
+    pub repeat: bool,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputEvent {
+    pub data: Option<EcoString>,
+    /// What kind of edit this is, such as `insertText`.
+    pub input_type: EcoString,
+}
+
+impl Event {
+    pub fn mouse(event_type: impl Into<EcoString>, detail: MouseEvent) -> Self {
+        Self {
+            event_type: event_type.into(),
+            bubbles: true,
+            cancelable: true,
+            detail: EventDetail::Mouse(detail),
+        }
+    }
+
+    pub fn keyboard(event_type: impl Into<EcoString>, detail: KeyboardEvent) -> Self {
+        Self {
+            event_type: event_type.into(),
+            bubbles: true,
+            cancelable: true,
+            detail: EventDetail::Keyboard(detail),
+        }
+    }
+
+    /// Only `focusin` and `focusout` bubble, `focus` and `blur` stay on their target.
+    pub fn focus(event_type: impl Into<EcoString>, related_target: Option<NodeId>) -> Self {
+        let event_type = event_type.into();
+        Self {
+            bubbles: event_type == "focusin" || event_type == "focusout",
+            event_type,
+            cancelable: false,
+            detail: EventDetail::Focus { related_target },
+        }
+    }
+
+    /// Only `beforeinput` can be cancelled, `input` reports an edit that has already happened.
+    pub fn input(event_type: impl Into<EcoString>, detail: InputEvent) -> Self {
+        let event_type = event_type.into();
+        Self {
+            cancelable: event_type == "beforeinput",
+            event_type,
+            bubbles: true,
+            detail: EventDetail::Input(detail),
+        }
+    }
+
+    pub fn custom(
+        event_type: impl Into<EcoString>,
+        detail: Rc<dyn Any>,
+        bubbles: bool,
+        cancelable: bool,
+    ) -> Self {
+        Self {
+            event_type: event_type.into(),
+            bubbles,
+            cancelable,
+            detail: EventDetail::Custom(detail),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+    Capturing,
+    AtTarget,
+    Bubbling,
+}
+
+#[derive(Default)]
+struct EventFlags {
+    stop_propagation: Cell<bool>,
+    stop_immediate_propagation: Cell<bool>,
+    canceled: Cell<bool>,
+}
+
+/// An event as one listener sees it. Stopping propagation or preventing the default action is
+/// shared with every other listener of the same dispatch.
+#[derive(Clone)]
+pub struct DispatchedEvent {
+    pub event: Rc<Event>,
+    /// The node the event was dispatched to.
+    pub target: NodeId,
+    /// The node whose listener this is.
+    pub current_target: NodeId,
+    pub phase: EventPhase,
+    flags: Rc<EventFlags>,
+}
+
+impl DispatchedEvent {
+    /// Let the remaining listeners on the current node run, but no others.
+    pub fn stop_propagation(&self) {
+        self.flags.stop_propagation.set(true);
+    }
+
+    /// Stop the event reaching any other listener at all.
+    pub fn stop_immediate_propagation(&self) {
+        self.flags.stop_propagation.set(true);
+        self.flags.stop_immediate_propagation.set(true);
+    }
+
+    /// Cancel the event's default action, if it can be cancelled.
+    pub fn prevent_default(&self) {
+        if self.event.cancelable {
+            self.flags.canceled.set(true);
+        }
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.flags.canceled.get()
+    }
+}
+
+/// Identifies a listener registered on a node, so that it can be removed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(usize);
+
+#[derive(Clone)]
+pub(crate) struct EventListener {
+    id: ListenerId,
+    event_type: EcoString,
+    capture: bool,
+    callback: Fwd<DispatchedEvent>,
+}
+
+/// The progress of one event through its propagation path.
+struct Dispatch {
+    event: Rc<Event>,
+    flags: Rc<EventFlags>,
+    /// The target followed by each of its ancestors.
+    path: Vec<NodeId>,
+    /// What remains of the path, as indices into it along with the phase to visit them in and
+    /// whether it is the capturing listeners or the rest that are called. The target is visited
+    /// twice, once for each.
+    steps: VecDeque<(usize, EventPhase, bool)>,
+    /// The node being visited, along with its listeners yet to be called.
+    current: Option<(usize, EventPhase, VecDeque<Fwd<DispatchedEvent>>)>,
+    done: Ret<bool>,
+}
+
+impl MjDom {
+    /// Call `listener` with every event of type `event_type` that reaches `node`, during the
+    /// capture phase if `capture` is set and otherwise during the bubble phase. Either way it
+    /// hears events dispatched to `node` itself.
+    pub fn add_event_listener(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        event_type: EcoString,
+        capture: bool,
+        listener: Fwd<DispatchedEvent>,
+        callback: Ret<ListenerId>,
+    ) {
+        let Some(entry) = self.tree.get_mut(node) else {
+            return;
+        };
+        let id = ListenerId(entry.next_listener);
+        entry.next_listener += 1;
+        entry.listeners.push(EventListener {
+            id,
+            event_type,
+            capture,
+            callback: listener,
+        });
+        ret!([callback], id);
+    }
+
+    pub fn remove_event_listener(&mut self, _cx: CX![], node: NodeId, listener: ListenerId) {
+        if let Some(entry) = self.tree.get_mut(node) {
+            entry
+                .listeners
+                .retain(|registered| registered.id != listener);
+        }
+    }
+
+    /// Dispatch `event` with `node` as its target, calling back with false if a listener
+    /// prevented its default action. The path is fixed when dispatch starts, so moving nodes
+    /// around from a listener doesn't change who else hears about the event.
+    pub fn dispatch_event(&mut self, cx: CX![], node: NodeId, event: Event, callback: Ret<bool>) {
+        if !self.tree.contains(node) {
+            return;
+        }
+        let path = [node]
+            .into_iter()
+            .chain(self.tree.ancestors(node))
+            .collect::<Vec<_>>();
+        let ancestors = 1..path.len();
+        let mut steps = ancestors
+            .clone()
+            .rev()
+            .map(|index| (index, EventPhase::Capturing, true))
+            .collect::<VecDeque<_>>();
+        steps.push_back((0, EventPhase::AtTarget, true));
+        steps.push_back((0, EventPhase::AtTarget, false));
+        if event.bubbles {
+            steps.extend(ancestors.map(|index| (index, EventPhase::Bubbling, false)));
+        }
+        let dispatch = Dispatch {
+            event: Rc::new(event),
+            flags: Rc::default(),
+            path,
+            steps,
+            current: None,
+            done: callback,
+        };
+        self.resume_dispatch(cx, dispatch);
+    }
+
+    /// Call the next listener of `dispatch`, moving along the path whenever the current node has
+    /// no more of them, and coming back here once the listener's message has been handled.
+    fn resume_dispatch(&mut self, cx: CX![], mut dispatch: Dispatch) {
+        loop {
+            if let Some((index, phase, listeners)) = &mut dispatch.current {
+                let listener = listeners.pop_front();
+                if let Some(listener) =
+                    listener.filter(|_| !dispatch.flags.stop_immediate_propagation.get())
+                {
+                    let event = DispatchedEvent {
+                        event: dispatch.event.clone(),
+                        target: dispatch.path[0],
+                        current_target: dispatch.path[*index],
+                        phase: *phase,
+                        flags: dispatch.flags.clone(),
+                    };
+                    fwd!([listener], event);
+                    // Queued behind the listener's message, so this only runs once it has
+                    call!([cx], resume_dispatch(dispatch));
+                    return;
+                }
+            }
+            let step = dispatch.steps.pop_front();
+            let Some((index, phase, capture)) =
+                step.filter(|_| !dispatch.flags.stop_propagation.get())
+            else {
+                ret!([dispatch.done], !dispatch.flags.canceled.get());
+                return;
+            };
+            let listeners = self.event_listeners(dispatch.path[index], &dispatch.event, capture);
+            dispatch.current = Some((index, phase, listeners));
+        }
+    }
+
+    /// The capturing listeners of `node` for `event` if `capture` is set, and otherwise the rest.
+    fn event_listeners(
+        &self,
+        node: NodeId,
+        event: &Event,
+        capture: bool,
+    ) -> VecDeque<Fwd<DispatchedEvent>> {
+        let Some(entry) = self.tree.get(node) else {
+            return VecDeque::new();
+        };
+        entry
+            .listeners
+            .iter()
+            .filter(|listener| {
+                listener.event_type == event.event_type && listener.capture == capture
+            })
+            .map(|listener| listener.callback.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,634 @@
+//! Forms: which form each control belongs to, and turning a form into the request submitting it
+//! makes. Submission only builds the request, and it is up to whoever asked to navigate with it.
+
+use std::{
+    borrow::Cow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use ecow::{eco_format, EcoString};
+use encoding_rs::Encoding;
+use html5ever::{namespace_url, ns};
+use stakker::{ret, Ret, CX};
+use url::{form_urlencoded, Url};
+
+use crate::{error::DomError, nodes::MemberKind, parser::NodeId, MjDom, DOCUMENT_NODE};
+
+/// Elements that can have a form owner and show up in the form's `elements`. Images can have a
+/// form owner too, but are never listed.
+const LISTED_ELEMENTS: [&str; 7] = [
+    "button", "fieldset", "input", "object", "output", "select", "textarea",
+];
+
+/// Elements that the `disabled` attribute turns off.
+const DISABLEABLE_ELEMENTS: [&str; 5] = ["button", "fieldset", "input", "select", "textarea"];
+
+/// How the form data set is encoded in the request body, from the `enctype` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormEnctype {
+    UrlEncoded,
+    Multipart,
+    TextPlain,
+}
+
+impl FormEnctype {
+    /// Any value that isn't one of the other two falls back to URL encoding.
+    pub fn from_attribute(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("multipart/form-data") {
+            Self::Multipart
+        } else if value.eq_ignore_ascii_case("text/plain") {
+            Self::TextPlain
+        } else {
+            Self::UrlEncoded
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormValue {
+    Text(EcoString),
+    File {
+        filename: EcoString,
+        content_type: EcoString,
+        data: Vec<u8>,
+    },
+}
+
+/// One name and value of the form data set, in the order the controls are in the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormEntry {
+    pub name: EcoString,
+    pub value: FormValue,
+}
+
+impl FormEntry {
+    fn text(name: impl Into<EcoString>, value: impl Into<EcoString>) -> Self {
+        Self {
+            name: name.into(),
+            value: FormValue::Text(value.into()),
+        }
+    }
+}
+
+/// The request submitting a form makes. A GET carries the form data set in the query of `url`,
+/// and a POST in `body`, encoded as `content_type` says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormSubmission {
+    Get {
+        url: Url,
+    },
+    Post {
+        url: Url,
+        content_type: String,
+        body: Vec<u8>,
+    },
+}
+
+impl FormSubmission {
+    pub fn url(&self) -> &Url {
+        match self {
+            Self::Get { url } | Self::Post { url, .. } => url,
+        }
+    }
+}
+
+fn html_element_name(kind: &MemberKind) -> Option<&str> {
+    kind.local_name()
+        .filter(|_| kind.is_html_element())
+        .map(|name| &**name)
+}
+
+fn is_form(kind: &MemberKind) -> bool {
+    kind.is_element(&ns!(html), "form")
+}
+
+pub fn is_listed(kind: &MemberKind) -> bool {
+    html_element_name(kind).is_some_and(|name| LISTED_ELEMENTS.contains(&name))
+}
+
+/// Whether `kind` is an element that can have a form owner.
+pub fn is_form_associated(kind: &MemberKind) -> bool {
+    is_listed(kind) || kind.is_element(&ns!(html), "img")
+}
+
+/// The `type` of an input or button, lowercased, with the default for a missing one.
+fn control_type(kind: &MemberKind) -> Option<EcoString> {
+    let default = match html_element_name(kind)? {
+        "input" => "text",
+        "button" => "submit",
+        _ => return None,
+    };
+    let control_type = kind
+        .attribute("type")
+        .map(|value| value.to_ascii_lowercase());
+    Some(control_type.unwrap_or_else(|| default.into()))
+}
+
+/// Whether `kind` submits its form when activated.
+pub fn is_submit_button(kind: &MemberKind) -> bool {
+    match (html_element_name(kind), control_type(kind).as_deref()) {
+        (Some("input"), Some(control_type)) => matches!(control_type, "submit" | "image"),
+        // A button of an unknown type is a submit button too
+        (Some("button"), Some(control_type)) => !matches!(control_type, "reset" | "button"),
+        _ => false,
+    }
+}
+
+fn is_button(kind: &MemberKind) -> bool {
+    match (html_element_name(kind), control_type(kind).as_deref()) {
+        (Some("input"), Some(control_type)) => {
+            matches!(control_type, "submit" | "image" | "reset" | "button")
+        }
+        (Some("button"), _) => true,
+        _ => false,
+    }
+}
+
+/// Turn lone carriage returns and line feeds into CRLF pairs, as every encoding of a form wants.
+fn normalize_newlines(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                normalized.push_str("\r\n");
+            }
+            '\n' => normalized.push_str("\r\n"),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Encode `entries` as `application/x-www-form-urlencoded`, as for both the query of a GET and the
+/// body of a POST.
+pub fn urlencode(entries: &[FormEntry], encoding: &'static Encoding) -> String {
+    let encode: &dyn Fn(&str) -> Cow<'_, [u8]> = &|text| encoding.encode(text).0;
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.encoding_override(Some(encode));
+    for entry in entries {
+        let value = match &entry.value {
+            FormValue::Text(value) => normalize_newlines(value),
+            FormValue::File { filename, .. } => filename.to_string(),
+        };
+        serializer.append_pair(&normalize_newlines(&entry.name), &value);
+    }
+    serializer.finish()
+}
+
+/// Encode `entries` as `multipart/form-data`, with each part separated by `boundary`.
+pub fn multipart(entries: &[FormEntry], encoding: &'static Encoding, boundary: &str) -> Vec<u8> {
+    // Quotes and line breaks would end the header early, so they are escaped in names
+    let escape = |name: &str| {
+        name.replace('"', "%22")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let mut body = vec![];
+    for entry in entries {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        let name = escape(&normalize_newlines(&entry.name));
+        let header = format!("Content-Disposition: form-data; name=\"{name}\"");
+        body.extend_from_slice(&encoding.encode(&header).0);
+        match &entry.value {
+            FormValue::Text(value) => {
+                body.extend_from_slice(b"\r\n\r\n");
+                body.extend_from_slice(&encoding.encode(&normalize_newlines(value)).0);
+            }
+            FormValue::File {
+                filename,
+                content_type,
+                data,
+            } => {
+                let header = format!(
+                    "; filename=\"{}\"\r\nContent-Type: {content_type}\r\n\r\n",
+                    escape(filename)
+                );
+                body.extend_from_slice(&encoding.encode(&header).0);
+                body.extend_from_slice(data);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    body
+}
+
+/// Encode `entries` as `text/plain`, one `name=value` line each. Nothing is escaped, so this is
+/// only meant for people to read.
+pub fn text_plain(entries: &[FormEntry], encoding: &'static Encoding) -> Vec<u8> {
+    let mut text = String::new();
+    for entry in entries {
+        let value = match &entry.value {
+            FormValue::Text(value) => value,
+            FormValue::File { filename, .. } => filename,
+        };
+        text.push_str(&normalize_newlines(&entry.name));
+        text.push('=');
+        text.push_str(&normalize_newlines(value));
+        text.push_str("\r\n");
+    }
+    encoding.encode(&text).0.into_owned()
+}
+
+/// A boundary for a multipart body. It only has to be unlikely to turn up in the body, so the
+/// random keys the standard library seeds its hashers with are enough.
+fn multipart_boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    format!("----MjFormBoundary{:016x}", hasher.finish())
+}
+
+impl MjDom {
+    /// The form `node` belongs to: the one its `form` attribute names, or else the one the parser
+    /// put it in while they are still in the same tree, or else the nearest form around it.
+    pub(crate) fn form_owner_of(&self, node: NodeId) -> Option<NodeId> {
+        let entry = self.tree.get(node)?;
+        if !is_form_associated(&entry.myself) {
+            return None;
+        }
+        if is_listed(&entry.myself) {
+            if let Some(id) = entry.myself.attribute("form") {
+                // The attribute wins even when it names nothing, leaving the element without one
+                return self
+                    .index
+                    .element_by_id(&self.tree, &id)
+                    .filter(|&form| self.tree.kind(form).is_some_and(is_form))
+                    .filter(|_| self.tree.root(node) == DOCUMENT_NODE);
+            }
+        }
+        let root = self.tree.root(node);
+        let associated = entry.form_owner.filter(|&form| {
+            self.tree.kind(form).is_some_and(is_form) && self.tree.root(form) == root
+        });
+        associated.or_else(|| {
+            self.tree
+                .ancestors(node)
+                .find(|&ancestor| self.tree.kind(ancestor).is_some_and(is_form))
+        })
+    }
+
+    /// Forget the forms the parser associated `node` and everything under it with now that it
+    /// has been taken out of its tree, along with the controls left behind by any form in it.
+    pub(crate) fn reset_form_owners(&mut self, node: NodeId) {
+        let detached = self.tree.subtree_nodes(node);
+        let forms = detached
+            .iter()
+            .copied()
+            .filter(|&node| self.tree.kind(node).is_some_and(is_form))
+            .collect::<Vec<_>>();
+        let mut stale = detached
+            .into_iter()
+            .filter(|&node| {
+                self.tree
+                    .get(node)
+                    .is_some_and(|entry| entry.form_owner.is_some())
+            })
+            .collect::<Vec<_>>();
+        if !forms.is_empty() {
+            let root = self.tree.root(node);
+            stale.extend(self.tree.descendants(DOCUMENT_NODE).filter(|&control| {
+                self.tree.root(control) != root
+                    && self.tree.get(control).is_some_and(|entry| {
+                        entry.form_owner.is_some_and(|form| forms.contains(&form))
+                    })
+            }));
+        }
+        for node in stale {
+            if let Some(entry) = self.tree.get_mut(node) {
+                entry.form_owner = None;
+            }
+        }
+    }
+
+    /// The listed elements `form` owns, in tree order, including image buttons.
+    fn associated_elements(&self, form: NodeId) -> Vec<NodeId> {
+        self.tree
+            .descendants(self.tree.root(form))
+            .filter(|&node| {
+                self.tree.kind(node).is_some_and(is_listed)
+                    && self.form_owner_of(node) == Some(form)
+            })
+            .collect()
+    }
+
+    /// Whether `node` is disabled, by its own `disabled` attribute or by being in a disabled
+    /// fieldset outside of that fieldset's first legend.
+    fn is_disabled(&self, node: NodeId) -> bool {
+        let disabled = |node: NodeId| {
+            self.tree.kind(node).is_some_and(|kind| {
+                html_element_name(kind).is_some_and(|name| DISABLEABLE_ELEMENTS.contains(&name))
+                    && kind.attribute("disabled").is_some()
+            })
+        };
+        if disabled(node) {
+            return true;
+        }
+        let mut child = node;
+        for ancestor in self.tree.ancestors(node) {
+            let in_fieldset = self
+                .tree
+                .kind(ancestor)
+                .is_some_and(|kind| kind.is_element(&ns!(html), "fieldset"));
+            if in_fieldset && disabled(ancestor) {
+                let first_legend = self.tree.children(ancestor).find(|&child| {
+                    self.tree
+                        .kind(child)
+                        .is_some_and(|kind| kind.is_element(&ns!(html), "legend"))
+                });
+                if first_legend != Some(child) {
+                    return true;
+                }
+            }
+            child = ancestor;
+        }
+        false
+    }
+
+    /// The values of the options of the select element `select` that are selected: those marked
+    /// `selected`, or else the first one that isn't disabled when only one can be chosen.
+    fn selected_options(&self, select: NodeId) -> Vec<EcoString> {
+        let multiple = self
+            .tree
+            .kind(select)
+            .is_some_and(|kind| kind.attribute("multiple").is_some());
+        let options = self
+            .tree
+            .descendants(select)
+            .filter(|&node| {
+                self.tree
+                    .kind(node)
+                    .is_some_and(|kind| kind.is_element(&ns!(html), "option"))
+            })
+            .collect::<Vec<_>>();
+        let option_disabled = |option: NodeId| {
+            let own = self
+                .tree
+                .kind(option)
+                .is_some_and(|kind| kind.attribute("disabled").is_some());
+            let group = self
+                .tree
+                .parent(option)
+                .and_then(|parent| self.tree.kind(parent));
+            own || group.is_some_and(|kind| {
+                kind.is_element(&ns!(html), "optgroup") && kind.attribute("disabled").is_some()
+            })
+        };
+        let mut selected = options
+            .iter()
+            .copied()
+            .filter(|&option| {
+                self.tree
+                    .kind(option)
+                    .is_some_and(|kind| kind.attribute("selected").is_some())
+            })
+            .collect::<Vec<_>>();
+        if !multiple {
+            // Without `multiple` only the last option marked selected is
+            selected = match selected.pop() {
+                Some(last) => vec![last],
+                None => options
+                    .iter()
+                    .copied()
+                    .find(|&option| !option_disabled(option))
+                    .into_iter()
+                    .collect(),
+            };
+        }
+        selected
+            .into_iter()
+            .filter(|&option| !option_disabled(option))
+            .map(|option| self.option_value(option))
+            .collect()
+    }
+
+    /// The `value` of an option, which is its text with whitespace collapsed if it has none.
+    fn option_value(&self, option: NodeId) -> EcoString {
+        if let Some(value) = self
+            .tree
+            .kind(option)
+            .and_then(|kind| kind.attribute("value"))
+        {
+            return value;
+        }
+        let text = self.tree.text_content(option);
+        let words = text.split_ascii_whitespace().collect::<Vec<_>>();
+        EcoString::from(words.join(" "))
+    }
+
+    /// The form data set of `form` as submitted by `submitter`: the name and value of every
+    /// control it owns that takes part, in tree order.
+    fn form_entries(
+        &self,
+        form: NodeId,
+        submitter: Option<NodeId>,
+        encoding: &'static Encoding,
+    ) -> Vec<FormEntry> {
+        let mut entries = vec![];
+        for field in self.associated_elements(form) {
+            let Some(kind) = self.tree.kind(field) else {
+                continue;
+            };
+            let in_datalist = self.tree.ancestors(field).any(|ancestor| {
+                self.tree
+                    .kind(ancestor)
+                    .is_some_and(|kind| kind.is_element(&ns!(html), "datalist"))
+            });
+            if in_datalist || self.is_disabled(field) {
+                continue;
+            }
+            if is_button(kind) && Some(field) != submitter {
+                continue;
+            }
+            let element_name = html_element_name(kind).unwrap_or_default();
+            let control_type = control_type(kind).unwrap_or_default();
+            let checkable =
+                element_name == "input" && matches!(&*control_type, "checkbox" | "radio");
+            if checkable && kind.attribute("checked").is_none() {
+                continue;
+            }
+            let name = kind.attribute("name").unwrap_or_default();
+            if element_name == "input" && control_type == "image" {
+                // The click isn't known, so it is taken to be at the top left corner
+                let prefix = match name.is_empty() {
+                    true => EcoString::new(),
+                    false => eco_format!("{name}."),
+                };
+                entries.push(FormEntry::text(eco_format!("{prefix}x"), "0"));
+                entries.push(FormEntry::text(eco_format!("{prefix}y"), "0"));
+                continue;
+            }
+            if element_name == "object" || name.is_empty() {
+                continue;
+            }
+            match (element_name, &*control_type) {
+                ("select", _) => {
+                    for value in self.selected_options(field) {
+                        entries.push(FormEntry::text(name.clone(), value));
+                    }
+                }
+                ("input", "checkbox" | "radio") => {
+                    let value = kind.attribute("value").unwrap_or_else(|| "on".into());
+                    entries.push(FormEntry::text(name, value));
+                }
+                ("input", "file") => {
+                    // Nothing is ever chosen, which is submitted as one empty file
+                    entries.push(FormEntry {
+                        name,
+                        value: FormValue::File {
+                            filename: EcoString::new(),
+                            content_type: "application/octet-stream".into(),
+                            data: vec![],
+                        },
+                    });
+                }
+                ("input", "hidden") if name.eq_ignore_ascii_case("_charset_") => {
+                    entries.push(FormEntry::text(name, encoding.name()));
+                }
+                ("textarea", _) => {
+                    let value = self.tree.text_content(field);
+                    entries.push(FormEntry::text(name, value));
+                }
+                _ => {
+                    let value = kind.attribute("value").unwrap_or_default();
+                    entries.push(FormEntry::text(name, value));
+                }
+            }
+            let has_dirname = element_name == "textarea"
+                || (element_name == "input" && matches!(&*control_type, "text" | "search"));
+            if let Some(dirname) = kind.attribute("dirname").filter(|_| has_dirname) {
+                if !dirname.is_empty() {
+                    entries.push(FormEntry::text(dirname, "ltr"));
+                }
+            }
+        }
+        entries
+    }
+
+    /// The attribute of `form` named `name`, unless `submitter` overrides it with its own
+    /// `formname`.
+    fn submission_attribute(
+        &self,
+        form: NodeId,
+        submitter: Option<NodeId>,
+        name: &str,
+    ) -> Option<EcoString> {
+        let overridden = submitter
+            .and_then(|submitter| self.tree.kind(submitter))
+            .and_then(|kind| kind.attribute(&format!("form{name}")));
+        overridden.or_else(|| self.tree.kind(form)?.attribute(name))
+    }
+
+    fn build_submission(
+        &self,
+        form: NodeId,
+        submitter: Option<NodeId>,
+    ) -> Result<FormSubmission, DomError> {
+        if !self.tree.kind(form).is_some_and(is_form) {
+            return Err(DomError::InvalidNodeType);
+        }
+        if let Some(submitter) = submitter {
+            if !self.tree.kind(submitter).is_some_and(is_submit_button) {
+                return Err(DomError::InvalidNodeType);
+            }
+            if self.form_owner_of(submitter) != Some(form) {
+                return Err(DomError::NotFound);
+            }
+        }
+        let action = self
+            .submission_attribute(form, submitter, "action")
+            .filter(|action| !action.is_empty());
+        // Without an action the form submits to the document itself, whatever its base
+        let mut url = match action {
+            Some(action) => self.resolve_url_value(&action)?,
+            None => self.url.clone(),
+        };
+        let post = self
+            .submission_attribute(form, submitter, "method")
+            .is_some_and(|method| method.eq_ignore_ascii_case("post"));
+        let enctype = self
+            .submission_attribute(form, submitter, "enctype")
+            .map_or(FormEnctype::UrlEncoded, |value| {
+                FormEnctype::from_attribute(&value)
+            });
+        // The first label of `accept-charset` that names an encoding, or else the document's own
+        let encoding = self
+            .tree
+            .kind(form)
+            .and_then(|kind| kind.attribute("accept-charset"))
+            .and_then(|labels| {
+                labels
+                    .split_ascii_whitespace()
+                    .find_map(|label| Encoding::for_label(label.as_bytes()))
+            })
+            .unwrap_or(self.encoding)
+            .output_encoding();
+        let entries = self.form_entries(form, submitter, encoding);
+        if !post {
+            url.set_query(Some(&urlencode(&entries, encoding)));
+            return Ok(FormSubmission::Get { url });
+        }
+        let (content_type, body) = match enctype {
+            FormEnctype::UrlEncoded => (
+                "application/x-www-form-urlencoded".to_string(),
+                urlencode(&entries, encoding).into_bytes(),
+            ),
+            FormEnctype::Multipart => {
+                let boundary = multipart_boundary();
+                (
+                    format!("multipart/form-data; boundary={boundary}"),
+                    multipart(&entries, encoding, &boundary),
+                )
+            }
+            FormEnctype::TextPlain => ("text/plain".to_string(), text_plain(&entries, encoding)),
+        };
+        Ok(FormSubmission::Post {
+            url,
+            content_type,
+            body,
+        })
+    }
+
+    /// The form `node` belongs to, if it is a form-associated element that has one.
+    pub fn form_owner(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        if self.tree.contains(node) {
+            ret!([callback], self.form_owner_of(node));
+        }
+    }
+
+    /// The controls `form` owns in tree order, as for its `elements` collection. Image buttons are
+    /// left out there, though they are still submitted.
+    pub fn form_elements(&mut self, _cx: CX![], form: NodeId, callback: Ret<Vec<NodeId>>) {
+        if !self.tree.contains(form) {
+            return;
+        }
+        let elements = self
+            .associated_elements(form)
+            .into_iter()
+            .filter(|&node| {
+                let kind = self.tree.kind(node);
+                !kind.is_some_and(|kind| {
+                    html_element_name(kind) == Some("input")
+                        && control_type(kind).as_deref() == Some("image")
+                })
+            })
+            .collect();
+        ret!([callback], elements);
+    }
+
+    /// The request submitting `form` with `submitter` would make, with the action resolved
+    /// against the document's base URL. Fails with [`DomError::InvalidNodeType`] if `form` isn't a form or
+    /// `submitter` isn't a submit button, and with [`DomError::NotFound`] if the submitter
+    /// belongs to another form.
+    pub fn submit_form(
+        &mut self,
+        _cx: CX![],
+        form: NodeId,
+        submitter: Option<NodeId>,
+        callback: Ret<Result<FormSubmission, DomError>>,
+    ) {
+        ret!([callback], self.build_submission(form, submitter));
+    }
+}
@@ -0,0 +1,241 @@
+//! Lookup tables from ids, class names and tag names to the elements carrying them, kept by
+//! [`MjDom`](crate::MjDom) so that those lookups don't have to walk the tree.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::Hash,
+};
+
+use ecow::EcoString;
+use html5ever::{local_name, namespace_url, ns, LocalName, Namespace};
+
+use crate::{
+    nodes::{attributes::qualified_name, MemberKind},
+    parser::{NodeId, DOCUMENT_NODE},
+    tree::DomTree,
+};
+
+/// The attribute values an element is indexed under, reported again whenever they change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct IndexKeys {
+    id: Option<EcoString>,
+    classes: Vec<EcoString>,
+}
+
+impl IndexKeys {
+    pub fn of(kind: &MemberKind) -> Self {
+        let id = kind
+            .attribute_ns(&ns!(), &local_name!("id"))
+            .filter(|id| !id.is_empty());
+        let classes = kind
+            .attribute_ns(&ns!(), &local_name!("class"))
+            .map(|classes| {
+                classes
+                    .split_ascii_whitespace()
+                    .map(EcoString::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { id, classes }
+    }
+}
+
+struct IndexedElement {
+    qualified_name: EcoString,
+    namespace: Namespace,
+    local_name: LocalName,
+    is_html: bool,
+    keys: IndexKeys,
+}
+
+/// Every element created for the current document by its id, classes and tag name. Lookups are
+/// given the tree so that they can leave out whatever isn't connected to the document, and put
+/// the rest in tree order.
+#[derive(Default)]
+pub(crate) struct ElementIndex {
+    elements: HashMap<NodeId, IndexedElement>,
+    ids: HashMap<EcoString, BTreeSet<NodeId>>,
+    classes: HashMap<EcoString, BTreeSet<NodeId>>,
+    tags: HashMap<EcoString, BTreeSet<NodeId>>,
+    local_names: HashMap<LocalName, BTreeSet<NodeId>>,
+}
+
+impl ElementIndex {
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn insert(&mut self, node: NodeId, kind: &MemberKind) {
+        let MemberKind::Element { name, .. } = kind else {
+            return;
+        };
+        let element = IndexedElement {
+            qualified_name: EcoString::from(qualified_name(name)),
+            namespace: name.ns.clone(),
+            local_name: name.local.clone(),
+            is_html: name.ns == ns!(html),
+            keys: IndexKeys::default(),
+        };
+        self.tags
+            .entry(element.qualified_name.clone())
+            .or_default()
+            .insert(node);
+        self.local_names
+            .entry(name.local.clone())
+            .or_default()
+            .insert(node);
+        self.elements.insert(node, element);
+        self.update(node, IndexKeys::of(kind));
+    }
+
+    /// Forget `node` entirely, as when its entry is dropped.
+    pub fn remove(&mut self, node: NodeId) {
+        self.update(node, IndexKeys::default());
+        if let Some(element) = self.elements.remove(&node) {
+            remove_from(&mut self.tags, &element.qualified_name, node);
+            remove_from(&mut self.local_names, &element.local_name, node);
+        }
+    }
+
+    /// Move `node` from the keys it was indexed under to `keys`.
+    pub fn update(&mut self, node: NodeId, keys: IndexKeys) {
+        let Some(element) = self.elements.get_mut(&node) else {
+            return;
+        };
+        let old = std::mem::replace(&mut element.keys, keys);
+        if let Some(id) = &old.id {
+            remove_from(&mut self.ids, id, node);
+        }
+        for class in &old.classes {
+            remove_from(&mut self.classes, class, node);
+        }
+        if let Some(id) = &element.keys.id {
+            self.ids.entry(id.clone()).or_default().insert(node);
+        }
+        for class in &element.keys.classes {
+            self.classes.entry(class.clone()).or_default().insert(node);
+        }
+    }
+
+    /// The first connected element with the id `id`.
+    pub fn element_by_id(&self, tree: &DomTree, id: &str) -> Option<NodeId> {
+        connected(tree, self.ids.get(id)?).into_iter().next()
+    }
+
+    /// Every connected element that has all of the space separated `class_names`. Quirks mode
+    /// documents compare class names ignoring ASCII case.
+    pub fn elements_by_class_name(
+        &self,
+        tree: &DomTree,
+        class_names: &str,
+        quirks: bool,
+    ) -> Vec<NodeId> {
+        let wanted = class_names.split_ascii_whitespace().collect::<Vec<_>>();
+        let Some(first) = wanted.first() else {
+            return vec![];
+        };
+        let same = |a: &str, b: &str| {
+            if quirks {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        };
+        let mut candidates = self
+            .classes
+            .iter()
+            .filter(|(class, _)| same(class, first))
+            .flat_map(|(_, nodes)| nodes)
+            .collect::<BTreeSet<_>>();
+        candidates.retain(|node| {
+            let classes = &self.elements[node].keys.classes;
+            wanted
+                .iter()
+                .all(|wanted| classes.iter().any(|class| same(class, wanted)))
+        });
+        connected(tree, candidates)
+    }
+
+    /// Every connected element with the qualified name `name`, or every connected element for
+    /// `*`. HTML elements are matched against the name lowercased.
+    pub fn elements_by_tag_name(&self, tree: &DomTree, name: &str) -> Vec<NodeId> {
+        if name == "*" {
+            let all = self.elements.keys().collect::<BTreeSet<_>>();
+            return connected(tree, all);
+        }
+        let lowercase = name.to_ascii_lowercase();
+        let exact = self.tags.get(name).into_iter().flatten();
+        let lowered = self.tags.get(&*lowercase).into_iter().flatten();
+        let candidates = exact
+            .chain(lowered)
+            .filter(|node| {
+                let element = &self.elements[node];
+                if element.is_html {
+                    element.qualified_name == lowercase
+                } else {
+                    element.qualified_name == name
+                }
+            })
+            .collect::<BTreeSet<_>>();
+        connected(tree, candidates)
+    }
+
+    /// Every connected element in `namespace` with the local name `local_name`, where `*` stands
+    /// for any namespace or any local name.
+    pub fn elements_by_tag_name_ns(
+        &self,
+        tree: &DomTree,
+        namespace: &Namespace,
+        local_name: &LocalName,
+    ) -> Vec<NodeId> {
+        let in_namespace =
+            |node: &&NodeId| &**namespace == "*" || self.elements[node].namespace == *namespace;
+        if &**local_name == "*" {
+            let all = self
+                .elements
+                .keys()
+                .filter(in_namespace)
+                .collect::<BTreeSet<_>>();
+            return connected(tree, all);
+        }
+        let candidates = self
+            .local_names
+            .get(local_name)
+            .into_iter()
+            .flatten()
+            .filter(in_namespace);
+        connected(tree, candidates)
+    }
+}
+
+/// The nodes among `nodes` that are connected to the document, in tree order. Ids are handed out
+/// as nodes are created, which is only tree order until anything moves, so more than one node is
+/// put in order by walking the document until all of them have turned up.
+fn connected<'a>(tree: &DomTree, nodes: impl IntoIterator<Item = &'a NodeId>) -> Vec<NodeId> {
+    let mut wanted = nodes.into_iter().copied().collect::<HashSet<_>>();
+    if wanted.len() <= 1 {
+        return wanted
+            .into_iter()
+            .filter(|&node| tree.is_inclusive_ancestor(DOCUMENT_NODE, node))
+            .collect();
+    }
+    let mut found = vec![];
+    for node in tree.descendants(DOCUMENT_NODE) {
+        if wanted.remove(&node) {
+            found.push(node);
+            if wanted.is_empty() {
+                break;
+            }
+        }
+    }
+    found
+}
+
+fn remove_from<K: Eq + Hash>(map: &mut HashMap<K, BTreeSet<NodeId>>, key: &K, node: NodeId) {
+    if let Some(nodes) = map.get_mut(key) {
+        nodes.remove(&node);
+        if nodes.is_empty() {
+            map.remove(key);
+        }
+    }
+}
@@ -1,65 +1,387 @@
-use std::{borrow::Cow, collections::HashMap, hash::Hash, io::BufReader};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
+use diagnostics::ParseDiagnostic;
 use dom_iterator::ForwardDomIterator;
 use ecow::EcoString;
-use html5ever::{
-    interface::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
-    parse_document,
-    tendril::{StrTendril, TendrilSink},
-    Attribute, ExpandedName, QualName,
-};
-use mj_utilities::{actor_in_map, actor_new_in_map, actor_own_map::ActorOwnMap};
-use nodes::{DomEntry, MemberKind};
-use parser::{MjDomParser, NodeId, ParseOperation};
-use stakker::{
-    actor, actor_in_slab, call, fwd_to, ret, ret_do, ret_nop, Actor, ActorOwn, ActorOwnSlab, Cx,
-    PipedLink, PipedThread, Ret, Share, CX,
+use encoding_rs::UTF_8;
+use error::DomError;
+use html5ever::{LocalName, Namespace};
+use index::ElementIndex;
+use mutation::{MutationKind, MutationRecord, Observer, ObserverId};
+use nodes::{DomEntry, MemberKind, Retained};
+use parser::{
+    is_xml_content_type, DocumentChunk, FragmentRequest, ParseOperation, ParserInput,
+    ParserNodeOrText, ParserPosition,
 };
+use selector::Selector;
+use snapshot::DomSnapshot;
+use stakker::{actor, fwd, fwd_to, lazy, ret, ret_nop, ActorOwn, Fwd, PipedThread, Ret, CX};
+use tree::DomTree;
+use url::Url;
+
+pub use encoding_rs::Encoding;
+pub use html5ever::interface::QuirksMode;
+pub use parser::{NodeId, DOCUMENT_NODE};
 
 // pub mod layout;
+pub mod diagnostics;
 pub mod dom_iterator;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod forms;
+mod index;
+pub mod mutation;
 pub mod nodes;
 pub mod parser;
+pub mod resolve;
+pub mod selector;
+pub mod serializer;
+pub mod shadow;
+pub mod snapshot;
+pub mod tree;
 
 pub struct MjDom {
-    document: Option<Actor<DomEntry>>,
-    nodes: ActorOwnMap<NodeId, DomEntry>,
-    parser: PipedThread<String, ParseOperation>,
+    tree: DomTree,
+    parser: PipedThread<ParserInput, Vec<ParseOperation>>,
+    quirks_mode: QuirksMode,
+    encoding: &'static Encoding,
+    /// The URL the document was fetched from, which is `about:blank` for one that wasn't.
+    url: Url,
+    /// Whether the document was served as XML and parsed with the XML parser.
+    xml: bool,
+    current_line: u64,
+    diagnostics: Vec<ParseDiagnostic>,
+    loaded: bool,
+    load_callbacks: Vec<Ret<()>>,
+    /// Waiting on each fragment sent to the parser, in the order they were sent.
+    fragment_callbacks: VecDeque<Ret<Result<(), DomError>>>,
+    /// Waiting on each shadow root the parser was asked to allocate, in the order they were asked.
+    shadow_callbacks: VecDeque<Ret<Result<NodeId, DomError>>>,
+    /// Documents started since the one the parser is currently sending operations for.
+    replaced_documents: usize,
+    index: ElementIndex,
+    observers: Vec<Observer>,
+    next_observer: usize,
+    /// Records waiting for the end of the current turn, along with who to deliver each one to.
+    pending_mutations: Vec<(ObserverId, MutationRecord)>,
+    /// Nodes detached this turn, to be released along with the rest of their tree at the end of
+    /// it unless they are back in the document or retained by then.
+    detached: Vec<NodeId>,
+    /// How many [`Retained`] handles there are for each entry.
+    retained: HashMap<NodeId, usize>,
+    /// Counts documents started, so that handles outliving their document are told apart.
+    generation: usize,
 }
 
 impl MjDom {
     pub fn init(cx: CX![]) -> Option<Self> {
         let dom = Self {
-            document: None,
-            nodes: ActorOwnMap::new(),
+            tree: DomTree::default(),
             parser: PipedThread::spawn(
-                fwd_to!([cx], recv() as (ParseOperation)),
+                fwd_to!([cx], recv() as (Vec<ParseOperation>)),
                 fwd_to!([cx], parser_terminated() as (Option<String>)),
                 cx,
-                move |link| {
-                    while let Some(message) = link.recv() {
-                        let parser = MjDomParser::new(link);
-                        parse_document(parser, Default::default())
-                            .from_utf8()
-                            .read_from(&mut BufReader::new(message.as_bytes()))
-                            .unwrap();
-                    }
-                },
+                parser::run,
             ),
+            quirks_mode: QuirksMode::NoQuirks,
+            encoding: UTF_8,
+            url: about_blank(),
+            xml: false,
+            current_line: 1,
+            diagnostics: vec![],
+            loaded: false,
+            load_callbacks: vec![],
+            fragment_callbacks: VecDeque::new(),
+            shadow_callbacks: VecDeque::new(),
+            replaced_documents: 0,
+            index: ElementIndex::default(),
+            observers: vec![],
+            next_observer: 0,
+            pending_mutations: vec![],
+            detached: vec![],
+            retained: HashMap::new(),
+            generation: 0,
         };
         Some(dom)
     }
 
-    pub fn parse_document(&mut self, cx: CX![], content: String) {
-        let document = actor_new_in_map!(self.nodes, cx, 0);
-        let root = document.clone();
-        let initializer = document.clone();
-        call!(
-            [initializer],
-            DomEntry::empty_of_kind(0, root, MemberKind::Document)
-        );
-        self.document = document.into();
-        self.parser.send(content);
+    /// Parse a complete document fetched from `url`, decoding `bytes` with the encoding sniffed
+    /// from them and from the `Content-Type` they were served with.
+    pub fn parse_document(
+        &mut self,
+        cx: CX![],
+        bytes: Vec<u8>,
+        content_type: Option<String>,
+        url: Option<Url>,
+    ) {
+        self.stream_document(cx, DocumentChunk::Start { content_type, url });
+        self.stream_document(cx, DocumentChunk::Data(bytes));
+        self.stream_document(cx, DocumentChunk::End);
+    }
+
+    /// Feed the next piece of a document as it arrives. A [`DocumentChunk::Start`] replaces the
+    /// current document with an empty one, which then grows as the parser gets through each
+    /// [`DocumentChunk::Data`] until [`DocumentChunk::End`].
+    pub fn stream_document(&mut self, _cx: CX![], chunk: DocumentChunk) {
+        if let DocumentChunk::Start { content_type, url } = &chunk {
+            // Tear down the previous tree, whatever the parser still has to say about it
+            self.reset_tree();
+            self.replaced_documents += 1;
+            self.url = url.clone().unwrap_or_else(about_blank);
+            self.xml = content_type.as_deref().is_some_and(is_xml_content_type);
+            self.quirks_mode = QuirksMode::NoQuirks;
+            self.encoding = UTF_8;
+            self.current_line = 1;
+            self.diagnostics.clear();
+            self.loaded = false;
+            self.fragment_callbacks.clear();
+            self.shadow_callbacks.clear();
+        }
+        self.parser.send(ParserInput::Chunk(chunk));
+    }
+
+    /// Replace the tree with one holding only an empty document.
+    fn reset_tree(&mut self) {
+        self.tree.clear();
+        self.index.clear();
+        // Observers were watching nodes of the old tree, whose ids are about to be reused
+        self.observers.clear();
+        self.detached.clear();
+        self.retained.clear();
+        self.generation += 1;
+        let document = DomEntry::new(DOCUMENT_NODE, MemberKind::Document, None);
+        self.tree.insert(document);
+    }
+
+    /// Call back once the document has been completely parsed, immediately if that has already
+    /// happened.
+    pub fn when_loaded(&mut self, _cx: CX![], callback: Ret<()>) {
+        if self.loaded {
+            ret!([callback], ());
+        } else {
+            self.load_callbacks.push(callback);
+        }
+    }
+
+    /// The tree as it stands, for reading it synchronously from within an
+    /// [`Actor::query`](stakker::Actor::query) rather than a message at a time.
+    pub fn tree(&self) -> &DomTree {
+        &self.tree
+    }
+
+    /// An immutable copy of the tree as it stands, which can be sent to another thread and read
+    /// there while the DOM carries on changing.
+    pub fn snapshot(&mut self, _cx: CX![], callback: Ret<Arc<DomSnapshot>>) {
+        ret!([callback], self.tree.snapshot());
+    }
+
+    /// The document's mode as decided by its doctype, which decides the quirks layout and style
+    /// have to apply.
+    pub fn quirks_mode(&mut self, _cx: CX![], callback: Ret<QuirksMode>) {
+        ret!([callback], self.quirks_mode);
+    }
+
+    /// Replace the children of `element` with `html` parsed in its context, as assigning
+    /// `innerHTML` does. `done` is called once the new children are in place.
+    pub fn set_inner_html(
+        &mut self,
+        _cx: CX![],
+        element: NodeId,
+        html: String,
+        done: Ret<Result<(), DomError>>,
+    ) {
+        let Some(kind) = self.tree.kind(element) else {
+            ret!([done], Err(DomError::NotFound));
+            return;
+        };
+        let MemberKind::Element { name, .. } = kind else {
+            ret!([done], Err(DomError::InvalidNodeType));
+            return;
+        };
+        let request = FragmentRequest {
+            context: element,
+            context_name: name.clone(),
+            quirks_mode: self.quirks_mode,
+            html,
+        };
+        self.fragment_callbacks.push_back(done);
+        self.parser.send(ParserInput::Fragment(request));
+    }
+
+    /// Report every change to `target` and its descendants to `observer`, which can be undone
+    /// with [`MjDom::disconnect`]. The records from each turn of the loop are delivered together
+    /// once the turn is over, so observers see the tree after it has settled.
+    pub fn observe(
+        &mut self,
+        _cx: CX![],
+        target: NodeId,
+        observer: Fwd<MutationRecord>,
+        callback: Ret<ObserverId>,
+    ) {
+        if !self.tree.contains(target) {
+            return;
+        }
+        let id = ObserverId(self.next_observer);
+        self.next_observer += 1;
+        self.observers.push(Observer {
+            id,
+            root: target,
+            records: observer,
+        });
+        ret!([callback], id);
+    }
+
+    /// Stop delivering records to an observer, including any still waiting for the end of this
+    /// turn.
+    pub fn disconnect(&mut self, _cx: CX![], observer: ObserverId) {
+        self.observers
+            .retain(|registered| registered.id != observer);
+    }
+
+    /// Keep `node` from being released while it is detached from the document, until the
+    /// returned handle is dropped.
+    pub fn retain(&mut self, cx: CX![], node: NodeId, callback: Ret<Retained>) {
+        if !self.tree.contains(node) {
+            return;
+        }
+        *self.retained.entry(node).or_default() += 1;
+        let retained = Retained {
+            node,
+            generation: self.generation,
+            release: fwd_to!([cx], unretain() as (usize, NodeId)),
+        };
+        ret!([callback], retained);
+    }
+
+    /// The first element in the document with the id `id`.
+    pub fn get_element_by_id(&mut self, _cx: CX![], id: EcoString, callback: Ret<Option<NodeId>>) {
+        ret!([callback], self.index.element_by_id(&self.tree, &id));
+    }
+
+    /// Every element in the document that has all of the space separated `class_names`.
+    pub fn get_elements_by_class_name(
+        &mut self,
+        _cx: CX![],
+        class_names: EcoString,
+        callback: Ret<Vec<NodeId>>,
+    ) {
+        let quirks = self.quirks_mode == QuirksMode::Quirks;
+        let found = self
+            .index
+            .elements_by_class_name(&self.tree, &class_names, quirks);
+        ret!([callback], found);
+    }
+
+    /// Every element in the document with the qualified name `qualified_name`, or every element
+    /// for `*`.
+    pub fn get_elements_by_tag_name(
+        &mut self,
+        _cx: CX![],
+        qualified_name: EcoString,
+        callback: Ret<Vec<NodeId>>,
+    ) {
+        let found = self.index.elements_by_tag_name(&self.tree, &qualified_name);
+        ret!([callback], found);
+    }
+
+    /// Every element in the document in `namespace` with the local name `local_name`. Either can
+    /// be `*` to match any namespace or any local name.
+    pub fn get_elements_by_tag_name_ns(
+        &mut self,
+        _cx: CX![],
+        namespace: Namespace,
+        local_name: LocalName,
+        callback: Ret<Vec<NodeId>>,
+    ) {
+        let found = self
+            .index
+            .elements_by_tag_name_ns(&self.tree, &namespace, &local_name);
+        ret!([callback], found);
+    }
+
+    /// The first element matching `selectors`, searching under `scope` or else the whole
+    /// document.
+    pub fn query_selector(
+        &mut self,
+        _cx: CX![],
+        scope: Option<NodeId>,
+        selectors: String,
+        callback: Ret<Result<Option<NodeId>, DomError>>,
+    ) {
+        let selector = match Selector::parse(&selectors) {
+            Ok(selector) => selector,
+            Err(error) => {
+                ret!([callback], Err(error));
+                return;
+            }
+        };
+        let found = selector.select(&self.tree, scope, self.quirks_mode).next();
+        ret!([callback], Ok(found));
+    }
+
+    /// Every element matching `selectors` in tree order, searching under `scope` or else the
+    /// whole document.
+    pub fn query_selector_all(
+        &mut self,
+        _cx: CX![],
+        scope: Option<NodeId>,
+        selectors: String,
+        callback: Ret<Result<Vec<NodeId>, DomError>>,
+    ) {
+        let selector = match Selector::parse(&selectors) {
+            Ok(selector) => selector,
+            Err(error) => {
+                ret!([callback], Err(error));
+                return;
+            }
+        };
+        let found = selector
+            .select(&self.tree, scope, self.quirks_mode)
+            .collect();
+        ret!([callback], Ok(found));
+    }
+
+    /// Whether `element` matches `selectors`. Anything that isn't an element never matches.
+    pub fn matches(
+        &mut self,
+        _cx: CX![],
+        element: NodeId,
+        selectors: String,
+        callback: Ret<Result<bool, DomError>>,
+    ) {
+        let selector = match Selector::parse(&selectors) {
+            Ok(selector) => selector,
+            Err(error) => {
+                ret!([callback], Err(error));
+                return;
+            }
+        };
+        let matches = selector.matches(&self.tree, element, self.quirks_mode);
+        ret!([callback], Ok(matches));
+    }
+
+    /// Whether the document is XML, such as XHTML or SVG, rather than HTML.
+    pub fn is_xml_document(&mut self, _cx: CX![], callback: Ret<bool>) {
+        ret!([callback], self.xml);
+    }
+
+    /// The character encoding the document was decoded with.
+    pub fn encoding(&mut self, _cx: CX![], callback: Ret<&'static Encoding>) {
+        ret!([callback], self.encoding);
+    }
+
+    /// Every parse error reported for the current document so far, in source order.
+    pub fn diagnostics(&mut self, _cx: CX![], callback: Ret<Vec<ParseDiagnostic>>) {
+        ret!([callback], self.diagnostics.clone());
+    }
+
+    /// Serialize the whole document back to HTML, doctype included.
+    pub fn document_html(&mut self, cx: CX![], callback: Ret<String>) {
+        self.inner_html(cx, DOCUMENT_NODE, callback);
     }
 
     pub fn iter(&mut self, cx: CX![], callback: Ret<ActorOwn<ForwardDomIterator>>) {
@@ -67,149 +389,455 @@ impl MjDom {
             [callback],
             actor!(
                 cx,
-                ForwardDomIterator::init(self.document.clone().expect("No document")),
+                ForwardDomIterator::init(self.tree.descendants(DOCUMENT_NODE).collect()),
                 ret_nop!()
-            ) as (ActorOwn<ForwardDomIterator>)
+            ) as ActorOwn<ForwardDomIterator>
         )
     }
 
-    fn recv(&mut self, cx: CX![], message: ParseOperation) {
-        match message {
-            ParseOperation::GetTemplateContents { target, contents } => todo!(),
+    fn recv(&mut self, cx: CX![], batch: Vec<ParseOperation>) {
+        for operation in batch {
+            self.apply(cx, operation);
+        }
+    }
+
+    fn apply(&mut self, cx: CX![], operation: ParseOperation) {
+        match operation {
+            ParseOperation::Begin => self.replaced_documents -= 1,
+            // Left over from a document that has since been replaced
+            _ if self.replaced_documents > 0 => {}
+            ParseOperation::GetTemplateContents { target, contents } => {
+                let mut fragment = DomEntry::new(
+                    contents,
+                    MemberKind::DocumentFragment,
+                    Some(self.current_line),
+                );
+                fragment.template_owner = Some(target);
+                self.tree.insert(fragment);
+                if let Some(target) = self.tree.get_mut(target) {
+                    target.template_contents = Some(contents);
+                }
+            }
             ParseOperation::CreateElement {
                 node,
                 name,
                 attrs,
                 current_line,
             } => {
-                actor_in_map!(
-                    self.nodes,
-                    cx,
-                    node,
-                    DomEntry::empty_of_kind(
-                        node,
-                        self.document.clone().expect("Document must be present"),
-                        MemberKind::Element {
-                            name,
-                            attrs: HashMap::new()
-                        }
-                    )
-                );
+                let kind = MemberKind::Element {
+                    name,
+                    attrs: attrs
+                        .into_iter()
+                        .map(|attr| (attr.name, attr.value))
+                        .collect(),
+                };
+                self.create_entry(cx, node, kind, current_line);
             }
             ParseOperation::CreateComment { text, node } => {
-                actor_in_map!(
-                    self.nodes,
-                    cx,
-                    node,
-                    DomEntry::empty_of_kind(
-                        node,
-                        self.document.clone().expect("Document must be present"),
-                        MemberKind::Comment {
-                            content: EcoString::from(text)
-                        }
-                    )
-                );
+                let kind = MemberKind::Comment { content: text };
+                self.create_entry(cx, node, kind, self.current_line);
+            }
+            ParseOperation::AppendBeforeSibling { node, position, .. }
+            | ParseOperation::AppendBasedOnParentNode { node, position, .. }
+            | ParseOperation::Append { node, position, .. } => {
+                self.insert_parsed(cx, node, position)
             }
-            ParseOperation::AppendBeforeSibling { sibling, node } => {
-                match node {
-                    parser::ParserNodeOrText::Node(node) => {
-                        let [sibling_actor, actor] = self.nodes.get_many_mut([&sibling, &node.id]);
-                        let sibling_actor =
-                            sibling_actor.expect("Could not find sibling element in DOM");
-                        let actor = actor.expect("Could not find element in DOM");
-                        let parent_resolver = sibling_actor.clone();
-                        let sibling_actor = sibling_actor.clone();
-                        let actor = actor.clone();
-                        call!(
-                            [parent_resolver],
-                            parent(ret_do!(move |parent: Option<Option<Actor<DomEntry>>>| {
-                                let parent = parent
-                                    .flatten()
-                                    .expect("Could not get parent of sibling node");
-                                call!([parent], insert_before(actor, sibling_actor))
-                            }))
-                        );
+            ParseOperation::AppendDoctypeToDocument {
+                node,
+                name,
+                public_id,
+                system_id,
+                position,
+            } => {
+                let kind = MemberKind::Doctype {
+                    name,
+                    public_id,
+                    system_id,
+                };
+                self.create_entry(cx, node, kind, self.current_line);
+                self.link(cx, node, &position);
+            }
+            ParseOperation::AddAttrsIfMissing { target, attrs } => {
+                self.change_attributes(cx, target, |kind| {
+                    if let MemberKind::Element {
+                        attrs: existing, ..
+                    } = kind
+                    {
+                        for attr in attrs {
+                            existing.entry(attr.name).or_insert_with(|| attr.value);
+                        }
                     }
-                    parser::ParserNodeOrText::Text(node_id, text) => {
-                        let actor = {
-                            actor_in_map!(
-                                self.nodes,
-                                cx,
-                                node_id,
-                                DomEntry::empty_of_kind(
-                                    node_id,
-                                    self.document.clone().expect("Document must be present"),
-                                    MemberKind::Text {
-                                        contents: EcoString::from(text)
-                                    }
-                                )
-                            )
-                        };
-                        let sibling_actor = self
-                            .nodes
-                            .get(&sibling)
-                            .expect("Could not find parent element in DOM");
-                        call!([sibling_actor], append(actor))
+                    ((), None)
+                });
+            }
+            ParseOperation::RemoveFromParent { target, position } => {
+                self.unlink(cx, target, &position)
+            }
+            ParseOperation::MarkScriptAlreadyStarted { .. } => {
+                // Scripts are never executed, so there is nothing to suppress
+            }
+            ParseOperation::ReparentChildren {
+                parent,
+                new_parent,
+                children,
+            } => {
+                self.queue_child_list(cx, parent, vec![], children.clone(), None, None);
+                for child in children {
+                    let position = ParserPosition {
+                        parent: new_parent,
+                        previous: self.tree.get(new_parent).and_then(|entry| entry.last_child),
+                        next: None,
+                    };
+                    self.link(cx, child, &position);
+                }
+            }
+            ParseOperation::AssociateWithForm { target, form } => {
+                if let Some(entry) = self.tree.get_mut(target) {
+                    entry.form_owner = Some(form);
+                }
+            }
+            ParseOperation::CreatePI { node, target, data } => {
+                let kind = MemberKind::ProcessingInstruction { target, data };
+                self.create_entry(cx, node, kind, self.current_line);
+            }
+            ParseOperation::AttachShadow {
+                host,
+                root,
+                template,
+                init,
+            } => {
+                let result = self.link_shadow_root(cx, host, root, template, init);
+                // Declarative shadow roots come from the parser, not from anyone waiting on them
+                if template.is_none() {
+                    if let Some(done) = self.shadow_callbacks.pop_front() {
+                        ret!([done], result.map(|()| root));
                     }
-                };
+                }
             }
-            ParseOperation::AppendBasedOnParentNode {
-                element,
-                prev_element,
-                node,
-            } => todo!(),
-            ParseOperation::Append { parent, node } => {
-                match node {
-                    parser::ParserNodeOrText::Node(node) => {
-                        let [parent_actor, actor] = self.nodes.get_many_mut([&parent, &node.id]);
-                        let parent_actor =
-                            parent_actor.expect("Could not find parent element in DOM");
-                        let actor = actor.expect("Could not find element in DOM");
-                        let actor = actor.clone();
-                        call!([parent_actor], append(actor));
+            ParseOperation::Pop { .. } => {}
+            ParseOperation::SetCurrentLine { line } => self.current_line = line,
+            ParseOperation::ParseError { message, line } => {
+                self.diagnostics.push(ParseDiagnostic {
+                    line,
+                    column: None,
+                    message: EcoString::from(message),
+                });
+            }
+            ParseOperation::Finish => {
+                self.loaded = true;
+                // Anything detached while the parser could still refer to it is let go now
+                if !self.detached.is_empty() {
+                    lazy!([cx], release_detached());
+                }
+                for callback in self.load_callbacks.drain(..) {
+                    ret!([callback], ());
+                }
+            }
+            ParseOperation::BeginFragment { root } => {
+                self.create_entry(cx, root, MemberKind::DocumentFragment, self.current_line);
+            }
+            ParseOperation::FinishFragment {
+                context,
+                root,
+                html,
+                children,
+            } => {
+                // The element may have been removed and released while its markup was parsed
+                if !self.tree.contains(context) {
+                    for node in children.into_iter().chain([html, root]) {
+                        for node in self.tree.subtree_nodes(node) {
+                            self.tree.remove(node);
+                            self.index.remove(node);
+                        }
                     }
-                    parser::ParserNodeOrText::Text(node_id, text) => {
-                        let actor = {
-                            actor_in_map!(
-                                self.nodes,
-                                cx,
-                                node_id,
-                                DomEntry::empty_of_kind(
-                                    node_id,
-                                    self.document.clone().expect("Document must be present"),
-                                    MemberKind::Text {
-                                        contents: EcoString::from(text)
-                                    }
-                                )
-                            )
-                        };
-                        let parent_actor = self
-                            .nodes
-                            .get(&parent)
-                            .expect("Could not find parent element in DOM");
-                        call!([parent_actor], append(actor))
+                    if let Some(done) = self.fragment_callbacks.pop_front() {
+                        ret!([done], Err(DomError::NotFound));
                     }
-                };
+                    return;
+                }
+                // A template's children are its contents, so they are replaced there instead
+                let parent = self
+                    .tree
+                    .get(context)
+                    .and_then(|entry| entry.template_contents)
+                    .unwrap_or(context);
+                let removed = self.tree.children(parent).collect::<Vec<_>>();
+                for &child in &removed {
+                    self.tree.detach(child);
+                    self.reset_form_owners(child);
+                }
+                for &child in &children {
+                    self.tree.attach(child, parent, None);
+                }
+                self.queue_child_list(cx, parent, children, removed.clone(), None, None);
+                for node in removed {
+                    self.schedule_release(cx, node);
+                }
+                for node in [html, root] {
+                    self.tree.remove(node);
+                    self.index.remove(node);
+                }
+                if let Some(done) = self.fragment_callbacks.pop_front() {
+                    ret!([done], Ok(()));
+                }
             }
-            ParseOperation::AppendDoctypeToDocument {
-                name,
-                public_id,
-                system_id,
-            } => todo!(),
-            ParseOperation::AddAttrsIfMissing { target, attrs } => todo!(),
-            ParseOperation::RemoveFromParent { target } => todo!(),
-            ParseOperation::MarkScriptAlreadyStarted { node } => todo!(),
-            ParseOperation::ReparentChildren { parent, new_parent } => todo!(),
-            ParseOperation::AssociateWithForm {
-                target,
-                form,
-                element,
-                prev_element,
-            } => todo!(),
-            ParseOperation::CreatePI { node, target, data } => todo!(),
-            ParseOperation::Pop { node } => todo!(),
-            ParseOperation::SetQuirksMode { mode } => todo!(),
+            ParseOperation::SetQuirksMode { mode } => self.quirks_mode = mode,
+            ParseOperation::SetEncoding { encoding } => self.encoding = encoding,
+        }
+    }
+
+    fn create_entry(&mut self, _cx: CX![], node: NodeId, kind: MemberKind, line: u64) {
+        self.index.insert(node, &kind);
+        self.tree.insert(DomEntry::new(node, kind, Some(line)));
+    }
+
+    /// Whether `child` may go under `parent` at all: only into something that can have children,
+    /// and never inside itself.
+    fn check_insert(&self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        let fits = child != DOCUMENT_NODE
+            && self
+                .tree
+                .kind(parent)
+                .is_some_and(MemberKind::can_have_children)
+            && !self.tree.is_inclusive_ancestor(child, parent);
+        fits.then_some(()).ok_or(DomError::HierarchyRequest)
+    }
+
+    pub(crate) fn insert_node(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        child: NodeId,
+        reference: Option<NodeId>,
+    ) -> Result<(), DomError> {
+        self.check_insert(parent, child)?;
+        if reference.is_some_and(|reference| self.tree.parent(reference) != Some(parent)) {
+            return Err(DomError::NotFound);
         }
+        // Inserting a node before itself leaves it where it is
+        let reference = match reference {
+            Some(reference) if reference == child => self.next_sibling_of(child),
+            reference => reference,
+        };
+        self.detach_node(cx, child);
+        let previous = match reference {
+            Some(reference) => self
+                .tree
+                .get(reference)
+                .and_then(|entry| entry.previous_sibling),
+            None => self.tree.get(parent).and_then(|entry| entry.last_child),
+        };
+        let position = ParserPosition {
+            parent,
+            previous,
+            next: reference,
+        };
+        self.link(cx, child, &position);
+        Ok(())
+    }
+
+    /// Detach `child` from `parent`, or from whatever parent it has if none is given.
+    pub(crate) fn remove_node(
+        &mut self,
+        cx: CX![],
+        parent: Option<NodeId>,
+        child: NodeId,
+    ) -> Result<(), DomError> {
+        if parent.is_some_and(|parent| self.tree.parent(child) != Some(parent)) {
+            return Err(DomError::NotFound);
+        }
+        if self.detach_node(cx, child) {
+            self.schedule_release(cx, child);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn replace_node(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        new_child: NodeId,
+        old_child: NodeId,
+    ) -> Result<(), DomError> {
+        if self.tree.parent(old_child) != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.check_insert(parent, new_child)?;
+        if new_child == old_child {
+            return Ok(());
+        }
+        let reference = match self.next_sibling_of(old_child) {
+            Some(next) if next == new_child => self.next_sibling_of(new_child),
+            next => next,
+        };
+        self.remove_node(cx, Some(parent), old_child)?;
+        self.insert_node(cx, parent, new_child, reference)
+    }
+
+    /// Take `node` away from its parent, closing the gap it leaves. Returns false if it had no
+    /// parent to begin with.
+    fn detach_node(&mut self, cx: CX![], node: NodeId) -> bool {
+        let Some(entry) = self.tree.get(node) else {
+            return false;
+        };
+        let Some(parent) = entry.parent else {
+            return false;
+        };
+        let position = ParserPosition {
+            parent,
+            previous: entry.previous_sibling,
+            next: entry.next_sibling,
+        };
+        self.unlink(cx, node, &position);
+        true
+    }
+
+    fn next_sibling_of(&self, node: NodeId) -> Option<NodeId> {
+        self.tree.get(node)?.next_sibling
+    }
+
+    fn schedule_release(&mut self, cx: CX![], node: NodeId) {
+        if self.detached.is_empty() && self.loaded {
+            lazy!([cx], release_detached());
+        }
+        self.detached.push(node);
+    }
+
+    /// Drop every tree detached this turn that is still out of the document and that nothing in
+    /// it is retained. The parser may still refer to entries until it is done with the document,
+    /// so nothing is released before then.
+    fn release_detached(&mut self, _cx: CX![]) {
+        if !self.loaded {
+            return;
+        }
+        for node in std::mem::take(&mut self.detached) {
+            // Anything in a shadow tree goes along with its host
+            let root = self.tree.shadow_including_root(node);
+            if root == DOCUMENT_NODE || !self.tree.contains(root) {
+                continue;
+            }
+            let released = self.tree.subtree_nodes(root);
+            if released.iter().any(|node| self.retained.contains_key(node)) {
+                continue;
+            }
+            for node in released {
+                self.tree.remove(node);
+                self.index.remove(node);
+            }
+        }
+    }
+
+    fn unretain(&mut self, cx: CX![], generation: usize, node: NodeId) {
+        if generation != self.generation {
+            return;
+        }
+        let Some(count) = self.retained.get_mut(&node) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.retained.remove(&node);
+            self.schedule_release(cx, node);
+        }
+    }
+
+    /// Hold on to `record` for every observer of a subtree it happened in, to be delivered once
+    /// this turn of the loop is over.
+    pub(crate) fn queue_mutation(&mut self, cx: CX![], record: MutationRecord) {
+        let interested = self
+            .observers
+            .iter()
+            .filter(|observer| {
+                self.tree
+                    .is_inclusive_ancestor(observer.root, record.target)
+            })
+            .map(|observer| observer.id)
+            .collect::<Vec<_>>();
+        if interested.is_empty() {
+            return;
+        }
+        if self.pending_mutations.is_empty() {
+            lazy!([cx], flush_mutations());
+        }
+        for observer in interested {
+            self.pending_mutations.push((observer, record.clone()));
+        }
+    }
+
+    fn flush_mutations(&mut self, _cx: CX![]) {
+        for (observer, record) in self.pending_mutations.drain(..) {
+            // Observers disconnected since the record was queued miss out on it
+            if let Some(observer) = self.observers.iter().find(|found| found.id == observer) {
+                fwd!([observer.records], record);
+            }
+        }
+    }
+
+    /// Queue a record of the children of `parent` changing.
+    fn queue_child_list(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        added: Vec<NodeId>,
+        removed: Vec<NodeId>,
+        previous_sibling: Option<NodeId>,
+        next_sibling: Option<NodeId>,
+    ) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let record = MutationRecord {
+            target: parent,
+            kind: MutationKind::ChildList {
+                added,
+                removed,
+                previous_sibling,
+                next_sibling,
+            },
+        };
+        self.queue_mutation(cx, record);
+    }
+
+    fn insert_parsed(&mut self, cx: CX![], node: ParserNodeOrText, position: ParserPosition) {
+        match node {
+            ParserNodeOrText::Node(node) => self.link(cx, node, &position),
+            ParserNodeOrText::Text(node_id, text) => {
+                if self.tree.contains(node_id) {
+                    self.append_text_content(cx, node_id, text);
+                    return;
+                }
+                let kind = MemberKind::Text { contents: text };
+                self.create_entry(cx, node_id, kind, self.current_line);
+                self.link(cx, node_id, &position);
+            }
+        }
+    }
+
+    /// Put `node` where the parser, or an edit, has decided it goes.
+    fn link(&mut self, cx: CX![], node: NodeId, position: &ParserPosition) {
+        self.tree.attach(node, position.parent, position.next);
+        self.queue_child_list(
+            cx,
+            position.parent,
+            vec![node],
+            vec![],
+            position.previous,
+            position.next,
+        );
+    }
+
+    /// The inverse of [`MjDom::link`], closing the gap `node` leaves behind at `position`.
+    fn unlink(&mut self, cx: CX![], node: NodeId, position: &ParserPosition) {
+        self.tree.detach(node);
+        self.reset_form_owners(node);
+        self.queue_child_list(
+            cx,
+            position.parent,
+            vec![],
+            vec![node],
+            position.previous,
+            position.next,
+        );
     }
 
     fn parser_terminated(&mut self, cx: CX![], panic: Option<String>) {
@@ -219,3 +847,8 @@ impl MjDom {
         cx.stop();
     }
 }
+
+/// The URL of a document that wasn't fetched from anywhere.
+fn about_blank() -> Url {
+    Url::parse("about:blank").expect("about:blank should parse")
+}
@@ -0,0 +1,47 @@
+//! Records of changes to the tree, delivered to observers registered with
+//! [`MjDom::observe`](crate::MjDom::observe).
+
+use ecow::EcoString;
+use html5ever::QualName;
+use stakker::Fwd;
+
+use crate::parser::NodeId;
+
+/// One change to the tree, as a `MutationObserver` would see it.
+#[derive(Debug, Clone)]
+pub struct MutationRecord {
+    /// The node whose children, attributes or text changed.
+    pub target: NodeId,
+    pub kind: MutationKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum MutationKind {
+    ChildList {
+        added: Vec<NodeId>,
+        removed: Vec<NodeId>,
+        /// The siblings either side of the added or removed nodes.
+        previous_sibling: Option<NodeId>,
+        next_sibling: Option<NodeId>,
+    },
+    Attributes {
+        name: QualName,
+        /// The value before the change, `None` if the attribute was just added.
+        old_value: Option<EcoString>,
+    },
+    CharacterData {
+        old_value: EcoString,
+    },
+}
+
+/// Identifies a registration made with [`MjDom::observe`](crate::MjDom::observe), so that it can
+/// be disconnected again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(pub(crate) usize);
+
+pub(crate) struct Observer {
+    pub id: ObserverId,
+    /// The root of the observed subtree.
+    pub root: NodeId,
+    pub records: Fwd<MutationRecord>,
+}
@@ -0,0 +1,359 @@
+use ecow::EcoString;
+use html5ever::{namespace_url, ns, LocalName, Namespace, Prefix, QualName};
+use indexmap::IndexMap;
+use stakker::{ret, Ret, CX};
+
+use crate::{
+    error::DomError,
+    index::IndexKeys,
+    mutation::{MutationKind, MutationRecord},
+    parser::NodeId,
+    MjDom,
+};
+
+use super::MemberKind;
+
+/// The name an attribute is addressed by in the non-namespaced APIs, e.g. `xlink:href`.
+pub fn qualified_name(name: &QualName) -> String {
+    match &name.prefix {
+        Some(prefix) => format!("{}:{}", prefix, name.local),
+        None => name.local.to_string(),
+    }
+}
+
+fn is_name_start_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == ':' || !c.is_ascii()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(is_name_start_char)
+        && chars.all(|c| is_name_start_char(c) || c.is_ascii_digit() || c == '-' || c == '.')
+}
+
+/// The DOM "validate and extract" steps, resolving a namespace and qualified name into a
+/// [`QualName`] or the error the DOM would throw.
+pub fn validate_and_extract(
+    namespace: Namespace,
+    qualified_name: &str,
+) -> Result<QualName, DomError> {
+    if !is_valid_name(qualified_name) {
+        return Err(DomError::InvalidCharacter(qualified_name.to_string()));
+    }
+    let (prefix, local) = match qualified_name.split_once(':') {
+        Some((prefix, local)) => {
+            if prefix.is_empty() || local.is_empty() || local.contains(':') {
+                return Err(DomError::InvalidCharacter(qualified_name.to_string()));
+            }
+            (Some(prefix), local)
+        }
+        None => (None, qualified_name),
+    };
+
+    let namespace_error = || Err(DomError::Namespace(qualified_name.to_string()));
+    if prefix.is_some() && namespace == ns!() {
+        return namespace_error();
+    }
+    if prefix == Some("xml") && namespace != ns!(xml) {
+        return namespace_error();
+    }
+    let is_xmlns = qualified_name == "xmlns" || prefix == Some("xmlns");
+    if is_xmlns != (namespace == ns!(xmlns)) {
+        return namespace_error();
+    }
+
+    Ok(QualName::new(
+        prefix.map(Prefix::from),
+        namespace,
+        LocalName::from(local),
+    ))
+}
+
+impl MemberKind {
+    /// Attribute names passed to the non-namespaced APIs are lowercased on HTML elements, matching
+    /// what the parser does to the source markup.
+    fn normalize_attribute_name(&self, qualified_name: &str) -> String {
+        if self.is_html_element() {
+            qualified_name.to_ascii_lowercase()
+        } else {
+            qualified_name.to_string()
+        }
+    }
+
+    fn attribute_key(&self, qualified_name: &str) -> Option<QualName> {
+        let Self::Element { attrs, .. } = self else {
+            return None;
+        };
+        let wanted = self.normalize_attribute_name(qualified_name);
+        attrs
+            .keys()
+            .find(|name| self::qualified_name(name) == wanted)
+            .cloned()
+    }
+
+    fn attribute_key_ns(&self, namespace: &Namespace, local_name: &LocalName) -> Option<QualName> {
+        let Self::Element { attrs, .. } = self else {
+            return None;
+        };
+        attrs
+            .keys()
+            .find(|name| name.ns == *namespace && name.local == *local_name)
+            .cloned()
+    }
+
+    pub fn attribute(&self, qualified_name: &str) -> Option<EcoString> {
+        let key = self.attribute_key(qualified_name)?;
+        self.attributes()?.get(&key).cloned()
+    }
+
+    pub fn attribute_ns(&self, namespace: &Namespace, local_name: &LocalName) -> Option<EcoString> {
+        let key = self.attribute_key_ns(namespace, local_name)?;
+        self.attributes()?.get(&key).cloned()
+    }
+
+    pub fn attributes(&self) -> Option<&IndexMap<QualName, EcoString>> {
+        match self {
+            Self::Element { attrs, .. } => Some(attrs),
+            _ => None,
+        }
+    }
+
+    /// Set the attribute `qualified_name`, returning the name it is stored under.
+    pub fn set_attribute(
+        &mut self,
+        qualified_name: &str,
+        value: EcoString,
+    ) -> Result<QualName, DomError> {
+        if !is_valid_name(qualified_name) {
+            return Err(DomError::InvalidCharacter(qualified_name.to_string()));
+        }
+        let key = self.attribute_key(qualified_name).unwrap_or_else(|| {
+            QualName::new(
+                None,
+                ns!(),
+                LocalName::from(self.normalize_attribute_name(qualified_name)),
+            )
+        });
+        if let Self::Element { attrs, .. } = self {
+            attrs.insert(key.clone(), value);
+        }
+        Ok(key)
+    }
+
+    /// Set the attribute named by `namespace` and `qualified_name`, returning the name it is
+    /// stored under.
+    pub fn set_attribute_ns(
+        &mut self,
+        namespace: Namespace,
+        qualified_name: &str,
+        value: EcoString,
+    ) -> Result<QualName, DomError> {
+        let name = validate_and_extract(namespace, qualified_name)?;
+        // An existing attribute keeps its prefix, only the value changes
+        let key = self.attribute_key_ns(&name.ns, &name.local).unwrap_or(name);
+        if let Self::Element { attrs, .. } = self {
+            attrs.insert(key.clone(), value);
+        }
+        Ok(key)
+    }
+
+    pub fn remove_attribute(&mut self, qualified_name: &str) -> Option<EcoString> {
+        let key = self.attribute_key(qualified_name)?;
+        match self {
+            Self::Element { attrs, .. } => attrs.shift_remove(&key),
+            _ => None,
+        }
+    }
+
+    pub fn remove_attribute_ns(
+        &mut self,
+        namespace: &Namespace,
+        local_name: &LocalName,
+    ) -> Option<EcoString> {
+        let key = self.attribute_key_ns(namespace, local_name)?;
+        match self {
+            Self::Element { attrs, .. } => attrs.shift_remove(&key),
+            _ => None,
+        }
+    }
+}
+
+impl MjDom {
+    pub fn get_attribute(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        qualified_name: EcoString,
+        callback: Ret<Option<EcoString>>,
+    ) {
+        self.answer(node, callback, |entry| {
+            entry.myself.attribute(&qualified_name)
+        });
+    }
+
+    pub fn get_attribute_ns(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        namespace: Namespace,
+        local_name: LocalName,
+        callback: Ret<Option<EcoString>>,
+    ) {
+        self.answer(node, callback, |entry| {
+            entry.myself.attribute_ns(&namespace, &local_name)
+        });
+    }
+
+    pub fn has_attribute(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        qualified_name: EcoString,
+        callback: Ret<bool>,
+    ) {
+        self.answer(node, callback, |entry| {
+            entry.myself.attribute_key(&qualified_name).is_some()
+        });
+    }
+
+    pub fn has_attribute_ns(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        namespace: Namespace,
+        local_name: LocalName,
+        callback: Ret<bool>,
+    ) {
+        self.answer(node, callback, |entry| {
+            entry
+                .myself
+                .attribute_key_ns(&namespace, &local_name)
+                .is_some()
+        });
+    }
+
+    pub fn set_attribute(
+        &mut self,
+        cx: CX![],
+        node: NodeId,
+        qualified_name: EcoString,
+        value: EcoString,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        let result = self.change_attributes(cx, node, |kind| {
+            setting(kind.set_attribute(&qualified_name, value))
+        });
+        if let Some(result) = result {
+            ret!([callback], result);
+        }
+    }
+
+    pub fn set_attribute_ns(
+        &mut self,
+        cx: CX![],
+        node: NodeId,
+        namespace: Namespace,
+        qualified_name: EcoString,
+        value: EcoString,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        let result = self.change_attributes(cx, node, |kind| {
+            setting(kind.set_attribute_ns(namespace, &qualified_name, value))
+        });
+        if let Some(result) = result {
+            ret!([callback], result);
+        }
+    }
+
+    pub fn remove_attribute(&mut self, cx: CX![], node: NodeId, qualified_name: EcoString) {
+        self.change_attributes(cx, node, |kind| {
+            (kind.remove_attribute(&qualified_name), None)
+        });
+    }
+
+    pub fn remove_attribute_ns(
+        &mut self,
+        cx: CX![],
+        node: NodeId,
+        namespace: Namespace,
+        local_name: LocalName,
+    ) {
+        self.change_attributes(cx, node, |kind| {
+            (kind.remove_attribute_ns(&namespace, &local_name), None)
+        });
+    }
+
+    /// Every attribute on `node`, empty for anything other than an element.
+    pub fn attributes(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        callback: Ret<Vec<(QualName, EcoString)>>,
+    ) {
+        self.answer(node, callback, |entry| {
+            entry
+                .myself
+                .attributes()
+                .map(|attrs| {
+                    attrs
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+    }
+
+    /// Apply `change` to the attributes of `node`, reporting each attribute it touched to
+    /// observers, and updating the index if the id or classes changed. Along with its result,
+    /// `change` returns the attribute it set if any, which is reported even if it already had
+    /// that value. Returns `None` if the node has been released.
+    pub(crate) fn change_attributes<R>(
+        &mut self,
+        cx: CX![],
+        node: NodeId,
+        change: impl FnOnce(&mut MemberKind) -> (R, Option<QualName>),
+    ) -> Option<R> {
+        let kind = &mut self.tree.get_mut(node)?.myself;
+        let before = kind.attributes().cloned().unwrap_or_default();
+        let keys_before = IndexKeys::of(kind);
+        let (result, set) = change(kind);
+        let keys = IndexKeys::of(kind);
+        let after = kind.attributes().cloned().unwrap_or_default();
+        if keys != keys_before {
+            self.index.update(node, keys);
+        }
+
+        let removed = before.keys().filter(|name| !after.contains_key(*name));
+        let changed = after
+            .iter()
+            .filter(|(name, value)| before.get(*name) != Some(value))
+            .map(|(name, _)| name);
+        let unchanged = set
+            .iter()
+            .filter(|name| after.contains_key(*name) && before.get(*name) == after.get(*name));
+        let names = removed
+            .chain(changed)
+            .chain(unchanged)
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in names {
+            let old_value = before.get(&name).cloned();
+            let record = MutationRecord {
+                target: node,
+                kind: MutationKind::Attributes { name, old_value },
+            };
+            self.queue_mutation(cx, record);
+        }
+        Some(result)
+    }
+}
+
+/// Split the outcome of setting an attribute into what its caller hears back and the attribute
+/// that was set, for [`MjDom::change_attributes`].
+fn setting(result: Result<QualName, DomError>) -> (Result<(), DomError>, Option<QualName>) {
+    match result {
+        Ok(name) => (Ok(()), Some(name)),
+        Err(error) => (Err(error), None),
+    }
+}
@@ -1 +1,161 @@
+use ecow::EcoString;
+use html5ever::{namespace_url, ns};
+use stakker::{ret, Ret, CX};
+use url::Url;
+
+use crate::{parser::NodeId, MjDom, DOCUMENT_NODE};
+
 pub struct MjDocument {}
+
+/// A `<meta>` element with a `content`, keyed by its `name` or its `http-equiv`. Both keys are
+/// lowercased, as they are compared ignoring ASCII case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaEntry {
+    pub node: NodeId,
+    pub name: Option<EcoString>,
+    pub http_equiv: Option<EcoString>,
+    pub content: EcoString,
+}
+
+/// A `<link>` element, with the `href` resolved against the document's base URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkEntry {
+    pub node: NodeId,
+    /// The link types in `rel`, lowercased.
+    pub rel: Vec<EcoString>,
+    pub href: Url,
+    pub media: Option<EcoString>,
+    pub mime_type: Option<EcoString>,
+    pub sizes: Option<EcoString>,
+}
+
+impl LinkEntry {
+    pub fn has_rel(&self, link_type: &str) -> bool {
+        self.rel.iter().any(|rel| rel == link_type)
+    }
+}
+
+impl MjDom {
+    /// The connected HTML elements named `local_name`, in tree order.
+    fn html_elements(&self, local_name: &'static str) -> impl Iterator<Item = NodeId> + '_ {
+        self.tree.descendants(DOCUMENT_NODE).filter(move |&node| {
+            self.tree
+                .kind(node)
+                .is_some_and(|kind| kind.is_element(&ns!(html), local_name))
+        })
+    }
+
+    /// The document's title. An SVG document takes it from the `<title>` directly under its root
+    /// as is, and anything else from its first HTML `<title>` with whitespace collapsed.
+    pub(crate) fn document_title(&self) -> String {
+        let root = self.tree.children(DOCUMENT_NODE).find(|&node| {
+            self.tree
+                .kind(node)
+                .is_some_and(|kind| kind.element_name().is_some())
+        });
+        let svg_root = root.filter(|&root| {
+            self.tree
+                .kind(root)
+                .is_some_and(|kind| kind.is_element(&ns!(svg), "svg"))
+        });
+        if let Some(root) = svg_root {
+            let title = self.tree.children(root).find(|&node| {
+                self.tree
+                    .kind(node)
+                    .is_some_and(|kind| kind.is_element(&ns!(svg), "title"))
+            });
+            return title.map_or_else(String::new, |title| self.tree.text_content(title));
+        }
+        let Some(title) = self.html_elements("title").next() else {
+            return String::new();
+        };
+        let text = self.tree.text_content(title);
+        text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// The URL relative URLs in the document are resolved against: the `href` of the first
+    /// `<base>` that has one, resolved against the document's URL, or else that URL itself.
+    pub(crate) fn document_base_url(&self) -> Url {
+        let first = self
+            .index
+            .elements_by_tag_name(&self.tree, "base")
+            .into_iter()
+            .find(|&node| {
+                self.tree.kind(node).is_some_and(|kind| {
+                    kind.is_element(&ns!(html), "base") && kind.attribute("href").is_some()
+                })
+            });
+        first
+            .and_then(|base| self.tree.kind(base)?.attribute("href"))
+            .and_then(|href| self.url.join(&href).ok())
+            .unwrap_or_else(|| self.url.clone())
+    }
+
+    /// The title of the document, as for `document.title`.
+    pub fn title(&mut self, _cx: CX![], callback: Ret<String>) {
+        ret!([callback], self.document_title());
+    }
+
+    /// The URL the document was fetched from, as for `document.URL`.
+    pub fn document_url(&mut self, _cx: CX![], callback: Ret<Url>) {
+        ret!([callback], self.url.clone());
+    }
+
+    /// The base URL of the document, as for `document.baseURI`.
+    pub fn base_url(&mut self, _cx: CX![], callback: Ret<Url>) {
+        ret!([callback], self.document_base_url());
+    }
+
+    /// Every `<meta>` in the document that has a `content` and a `name` or `http-equiv`, in tree
+    /// order.
+    pub fn meta(&mut self, _cx: CX![], callback: Ret<Vec<MetaEntry>>) {
+        let entries = self
+            .html_elements("meta")
+            .filter_map(|node| {
+                let kind = self.tree.kind(node)?;
+                let key = |name: &str| kind.attribute(name).map(|value| value.to_ascii_lowercase());
+                let (name, http_equiv) = (key("name"), key("http-equiv"));
+                if name.is_none() && http_equiv.is_none() {
+                    return None;
+                }
+                Some(MetaEntry {
+                    node,
+                    name,
+                    http_equiv,
+                    content: kind.attribute("content")?,
+                })
+            })
+            .collect();
+        ret!([callback], entries);
+    }
+
+    /// Every `<link>` in the document with both a `rel` and an `href` that resolves, in tree
+    /// order.
+    pub fn links(&mut self, _cx: CX![], callback: Ret<Vec<LinkEntry>>) {
+        let base_url = self.document_base_url();
+        let entries = self
+            .html_elements("link")
+            .filter_map(|node| {
+                let kind = self.tree.kind(node)?;
+                let rel = kind
+                    .attribute("rel")?
+                    .split_ascii_whitespace()
+                    .map(|rel| rel.to_ascii_lowercase().into())
+                    .collect::<Vec<_>>();
+                let href = kind.attribute("href").filter(|href| !href.is_empty())?;
+                if rel.is_empty() {
+                    return None;
+                }
+                Some(LinkEntry {
+                    node,
+                    rel,
+                    href: base_url.join(&href).ok()?,
+                    media: kind.attribute("media"),
+                    mime_type: kind.attribute("type"),
+                    sizes: kind.attribute("sizes"),
+                })
+            })
+            .collect();
+        ret!([callback], entries);
+    }
+}
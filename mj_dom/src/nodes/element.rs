@@ -0,0 +1,113 @@
+use html5ever::{namespace_url, ns, LocalName, Namespace, QualName};
+use stakker::{Ret, CX};
+
+use crate::{parser::NodeId, MjDom};
+
+use super::{attributes::qualified_name, DomEntry, MemberKind};
+
+/// Which markup language an element belongs to, going by its namespace. Inline `<svg>` and
+/// `<math>` in an HTML document are parsed into their own namespaces, and each is laid out and
+/// painted by its own rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementNamespace {
+    Html,
+    Svg,
+    MathMl,
+    Other,
+}
+
+impl ElementNamespace {
+    pub fn of(namespace: &Namespace) -> Self {
+        if *namespace == ns!(html) {
+            Self::Html
+        } else if *namespace == ns!(svg) {
+            Self::Svg
+        } else if *namespace == ns!(mathml) {
+            Self::MathMl
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl MemberKind {
+    /// The element's name, or `None` for anything that isn't an element.
+    pub fn element_name(&self) -> Option<&QualName> {
+        match self {
+            Self::Element { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn namespace(&self) -> Option<&Namespace> {
+        self.element_name().map(|name| &name.ns)
+    }
+
+    pub fn local_name(&self) -> Option<&LocalName> {
+        self.element_name().map(|name| &name.local)
+    }
+
+    pub fn element_namespace(&self) -> Option<ElementNamespace> {
+        self.namespace().map(ElementNamespace::of)
+    }
+
+    /// Whether this is the element `local_name` in `namespace`. Foreign elements keep the case
+    /// the parser adjusted them to, so SVG's `clipPath` is not `clippath`.
+    pub fn is_element(&self, namespace: &Namespace, local_name: &str) -> bool {
+        self.element_name()
+            .is_some_and(|name| name.ns == *namespace && &*name.local == local_name)
+    }
+
+    pub fn is_html_element(&self) -> bool {
+        self.element_namespace() == Some(ElementNamespace::Html)
+    }
+
+    pub fn is_svg_element(&self) -> bool {
+        self.element_namespace() == Some(ElementNamespace::Svg)
+    }
+
+    pub fn is_mathml_element(&self) -> bool {
+        self.element_namespace() == Some(ElementNamespace::MathMl)
+    }
+
+    /// The DOM `tagName`: the qualified name, uppercased for HTML elements in an HTML document.
+    pub fn tag_name(&self, html_document: bool) -> Option<String> {
+        let name = self.element_name()?;
+        let tag_name = qualified_name(name);
+        Some(if html_document && name.ns == ns!(html) {
+            tag_name.to_ascii_uppercase()
+        } else {
+            tag_name
+        })
+    }
+}
+
+impl DomEntry {
+    pub fn element_name(&self) -> Option<&QualName> {
+        self.myself.element_name()
+    }
+
+    pub fn element_namespace(&self) -> Option<ElementNamespace> {
+        self.myself.element_namespace()
+    }
+
+    pub fn is_element(&self, namespace: &Namespace, local_name: &str) -> bool {
+        self.myself.is_element(namespace, local_name)
+    }
+}
+
+impl MjDom {
+    /// The namespace of the element `node`, as for `namespaceURI`.
+    pub fn namespace_uri(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<Namespace>>) {
+        self.answer(node, callback, |entry| entry.myself.namespace().cloned());
+    }
+
+    pub fn local_name(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<LocalName>>) {
+        self.answer(node, callback, |entry| entry.myself.local_name().cloned());
+    }
+
+    pub fn tag_name(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<String>>) {
+        let html_document = !self.xml;
+        self.answer(node, callback, |entry| entry.myself.tag_name(html_document));
+    }
+}
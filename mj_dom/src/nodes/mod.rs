@@ -1,19 +1,35 @@
-use std::collections::{HashMap, VecDeque};
-
 use ecow::EcoString;
 use html5ever::QualName;
-use stakker::{call, ret, ret_do, ret_some_do, ret_to, stop, Actor, Ret, CX};
-
-use crate::parser::NodeId;
-
+use indexmap::IndexMap;
+use stakker::{fwd, ret, Fwd, Ret, CX};
+
+use crate::{
+    error::DomError,
+    events::EventListener,
+    mutation::{MutationKind, MutationRecord},
+    parser::NodeId,
+    serializer,
+    shadow::ShadowRoot,
+    MjDom,
+};
+
+pub mod attributes;
 pub mod document;
+pub mod element;
 
 #[derive(Debug, Clone)]
 pub enum MemberKind {
     Document,
+    DocumentFragment,
+    Doctype {
+        name: EcoString,
+        public_id: EcoString,
+        system_id: EcoString,
+    },
     Element {
         name: QualName,
-        attrs: HashMap<QualName, EcoString>,
+        /// In the order they were added to the element, which is the order they serialize in.
+        attrs: IndexMap<QualName, EcoString>,
     },
     Comment {
         content: EcoString,
@@ -21,6 +37,10 @@ pub enum MemberKind {
     Text {
         contents: EcoString,
     },
+    ProcessingInstruction {
+        target: EcoString,
+        data: EcoString,
+    },
 }
 
 impl MemberKind {
@@ -32,244 +52,321 @@ impl MemberKind {
         }
     }
 
-    pub fn append_text_content(&mut self, new_suffix: &str) {
+    /// Append `new_suffix` to the contents of a text node, returning whether this is one.
+    pub fn append_text_content(&mut self, new_suffix: &str) -> bool {
         if let Self::Text { ref mut contents } = self {
-            dbg!(&contents);
-            dbg!(&new_suffix);
-            contents.push_str(new_suffix)
+            contents.push_str(new_suffix);
+            true
         } else {
-            unimplemented!()
+            false
         }
     }
 
+    /// Whether a node of this kind can have children at all, which only documents, fragments
+    /// and elements can.
+    pub fn can_have_children(&self) -> bool {
+        matches!(
+            self,
+            Self::Document | Self::DocumentFragment | Self::Element { .. }
+        )
+    }
+
     pub fn is_text(&mut self) -> bool {
-        match self {
-            Self::Text { .. } => true,
-            _ => false,
+        matches!(self, Self::Text { .. })
+    }
+}
+
+/// An owned copy of a [`DomEntry`] and all of its descendants, for looking at a whole subtree
+/// outside of [`MjDom`].
+#[derive(Clone)]
+pub struct DomSubtree {
+    pub id: NodeId,
+    pub kind: MemberKind,
+    pub children: Vec<DomSubtree>,
+    pub template_contents: Option<Box<DomSubtree>>,
+}
+
+impl DomSubtree {
+    /// The node with the given id at or under this one, template contents included.
+    pub fn find(&self, id: NodeId) -> Option<&DomSubtree> {
+        if self.id == id {
+            return Some(self);
         }
+        self.template_contents
+            .iter()
+            .map(|contents| &**contents)
+            .chain(&self.children)
+            .find_map(|child| child.find(id))
+    }
+}
+
+/// Keeps an entry from being released while it is detached from the document, along with
+/// everything else in the tree it is part of. Entries that are detached and not retained are
+/// released at the end of the turn, unless they have been put back into the document by then.
+pub struct Retained {
+    pub node: NodeId,
+    pub(crate) generation: usize,
+    pub(crate) release: Fwd<(usize, NodeId)>,
+}
+
+impl Drop for Retained {
+    fn drop(&mut self) {
+        fwd!([self.release], (self.generation, self.node));
     }
 }
 
+/// One node of the document, kept in the [`DomTree`](crate::tree::DomTree) and linked to its
+/// neighbours by id.
 #[derive(Clone)]
 pub struct DomEntry {
     pub id: NodeId,
-    pub root: Actor<DomEntry>,
-    pub parent: Option<Actor<DomEntry>>,
-    pub first_child: Option<Actor<DomEntry>>,
-    pub last_child: Option<Actor<DomEntry>>,
-    pub previous_sibling: Option<Actor<DomEntry>>,
-    pub next_sibling: Option<Actor<DomEntry>>,
+    pub parent: Option<NodeId>,
+    pub first_child: Option<NodeId>,
+    pub last_child: Option<NodeId>,
+    pub previous_sibling: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+    /// The fragment holding a template element's contents. It is not a child of the template, so
+    /// walking the tree through the child and sibling links never enters it.
+    pub template_contents: Option<NodeId>,
+    /// For a fragment holding a template's contents, the template it belongs to.
+    pub template_owner: Option<NodeId>,
+    /// The shadow root attached to this element. Like template contents, it is not a child.
+    pub shadow_root: Option<NodeId>,
+    /// For a fragment that is a shadow root, the host it is attached to and how.
+    pub shadow: Option<ShadowRoot>,
+    /// The line of the source markup this entry was parsed from, if it came from the parser.
+    pub source_line: Option<u64>,
+    /// The form the parser associated this element with. It only counts as the form owner while
+    /// both are in the same tree, see [`MjDom::form_owner`].
+    pub(crate) form_owner: Option<NodeId>,
     pub myself: MemberKind,
+    pub(crate) listeners: Vec<EventListener>,
+    pub(crate) next_listener: usize,
 }
 
 impl DomEntry {
-    pub fn empty_of_kind(
-        cx: CX![],
-        id: NodeId,
-        root: Actor<DomEntry>,
-        kind: MemberKind,
-    ) -> Option<Self> {
-        Some(Self {
+    pub(crate) fn new(id: NodeId, kind: MemberKind, source_line: Option<u64>) -> Self {
+        Self {
             id,
-            root,
             parent: None,
             first_child: None,
             last_child: None,
             previous_sibling: None,
             next_sibling: None,
+            template_contents: None,
+            template_owner: None,
+            shadow_root: None,
+            shadow: None,
+            source_line,
+            form_owner: None,
             myself: kind,
-        })
+            listeners: vec![],
+            next_listener: 0,
+        }
     }
 
-    fn is_text(&mut self, cx: CX![], callback: Ret<bool>) {
-        ret!([callback], self.myself.is_text())
+    pub fn debug(&self) {
+        match &self.myself {
+            MemberKind::Document => {
+                dbg!("Document Root");
+            }
+            MemberKind::DocumentFragment => {
+                dbg!("Document Fragment");
+            }
+            MemberKind::Doctype { name, .. } => {
+                dbg!(name);
+            }
+            MemberKind::Element { name, .. } => {
+                dbg!(name);
+            }
+            MemberKind::Comment { content } => {
+                dbg!(content);
+            }
+            MemberKind::Text { contents } => {
+                dbg!(contents);
+            }
+            MemberKind::ProcessingInstruction { target, data } => {
+                dbg!(target, data);
+            }
+        };
     }
 }
 
-impl DomEntry {
-    pub fn append(&mut self, cx: CX![], other: Actor<DomEntry>) {
-        let new_child = other.clone();
-        call!([new_child], set_parent(cx.this().clone().into()));
-        if self.first_child.is_none() {
-            self.first_child = Some(new_child.clone());
-            self.last_child = Some(new_child);
-            return;
+/// Reading the tree one node at a time. As with a message to an actor that has gone, asking about
+/// a node that has been released drops the callback unanswered.
+impl MjDom {
+    /// Answer `callback` with what `answer` makes of the entry for `node`, if it is still there.
+    pub(crate) fn answer<T>(
+        &self,
+        node: NodeId,
+        callback: Ret<T>,
+        answer: impl FnOnce(&DomEntry) -> T,
+    ) {
+        if let Some(entry) = self.tree.get(node) {
+            ret!([callback], answer(entry));
         }
-
-        let sibling = other.clone().into();
-        let current_last_child = self
-            .last_child
-            .clone()
-            .expect("Last child cannot be none if the first child is populated");
-
-        call!([current_last_child], set_next_sibling(sibling));
-        call!(
-            [other],
-            set_previous_sibling(current_last_child.clone().into())
-        );
-        self.last_child = Some(other.clone());
-        call!([current_last_child], maybe_merge(other))
     }
 
-    pub fn insert_before(&mut self, cx: CX![], other: Actor<DomEntry>, next: Actor<DomEntry>) {
-        let ret = ret_to!(
-            [cx],
-            insert_resolve_relationships(other.clone(), next.clone()) as (Option<Actor<DomEntry>>)
-        );
-        call!([other], set_parent(cx.this().clone().into()));
-        call!([next], previous_sibling(ret))
+    /// Whether `node` is still there, rather than released.
+    pub fn contains(&mut self, _cx: CX![], node: NodeId, callback: Ret<bool>) {
+        ret!([callback], self.tree.contains(node));
     }
 
-    fn insert_resolve_relationships(
-        &mut self,
-        _cx: CX![],
-        between: Actor<DomEntry>,
-        next: Actor<DomEntry>,
-        previous: Option<Option<Actor<DomEntry>>>,
-    ) {
-        if let Some(previous) = previous.flatten() {
-            let new_sibling = previous.clone();
-            let to_insert = between.clone();
-            call!([new_sibling], set_next_sibling(between.clone().into()));
-            call!([to_insert], set_previous_sibling(previous.clone().into()));
-            call!([previous], maybe_merge(to_insert));
-        } else {
-            // If the node didn't have a sibling to the left, it was the first one
-            self.first_child = between.clone().into();
-        }
-        let to_insert = between.clone();
-        call!([to_insert], set_next_sibling(next.clone().into()));
-        call!([next], set_previous_sibling(between.into()));
+    pub fn kind(&mut self, _cx: CX![], node: NodeId, callback: Ret<MemberKind>) {
+        self.answer(node, callback, |entry| entry.myself.clone());
     }
 
-    fn maybe_merge(&mut self, cx: CX![], other: Actor<DomEntry>) {
-        if !self.myself.is_text() {
-            return;
-        }
-
-        let this = cx.this().clone();
-        let to_merge = other.clone();
-        let is_text_cb = ret_some_do!(move |other_is_text: bool| {
-            if !other_is_text {
-                return;
-            }
-            call!([this], merge(to_merge))
-        });
-
-        call!([other], is_text(is_text_cb));
+    pub fn parent(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.parent);
     }
 
-    fn merge(&mut self, cx: CX![], other: Actor<DomEntry>) {
-        let this = cx.this().clone();
-        let bunk = other.clone();
-        let text_cb = ret_some_do!(move |other_text_content: EcoString| {
-            call!([this], append_text_content(other_text_content));
-            call!([bunk], remove_self());
-        });
-        call!([other], text_content(text_cb));
+    pub fn previous_sibling(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.previous_sibling);
     }
 
-    pub fn remove_child(&mut self, cx: CX![], child: Actor<DomEntry>) {
-        // TODO: Need to verify that yes, the child is actually one of self's
-        call!([child], remove_self())
+    pub fn next_sibling(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.next_sibling);
     }
 
-    fn remove_self(&mut self, cx: CX![]) {
-        println!("Removing self");
-        self.debug(cx);
-        let parent = self
-            .parent
-            .clone()
-            .expect("Trying to remove a parentless entry");
-        if let Some(previous_sibling) = &self.previous_sibling {
-            call!(
-                [previous_sibling],
-                set_next_sibling(self.next_sibling.clone())
-            );
-        }
-
-        if let Some(next_sibling) = &self.next_sibling {
-            call!(
-                [next_sibling],
-                set_previous_sibling(self.previous_sibling.clone())
-            );
-        }
+    pub fn first_child(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.first_child);
+    }
 
-        // stop!(cx);
+    pub fn last_child(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.last_child);
     }
 
-    pub fn debug(&mut self, cx: CX![]) {
-        match &self.myself {
-            MemberKind::Document => {
-                dbg!("Document Root");
-            }
-            MemberKind::Element { name, attrs } => {
-                dbg!(name);
-            }
-            MemberKind::Comment { content } => {
-                dbg!(content);
-            }
-            MemberKind::Text { contents } => {
-                dbg!(contents);
-            }
-        };
+    pub fn template_contents(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(node, callback, |entry| entry.template_contents);
     }
-}
 
-impl DomEntry {
-    pub fn set_parent(&mut self, cx: CX![], other: Option<Actor<DomEntry>>) {
-        self.parent = other;
+    pub fn source_line(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<u64>>) {
+        self.answer(node, callback, |entry| entry.source_line);
     }
 
-    pub fn parent(&mut self, cx: CX![], callback: Ret<Option<Actor<DomEntry>>>) {
-        ret!([callback], self.parent.clone())
+    /// An owned copy of `node` and its descendants, or of the whole document for
+    /// [`DOCUMENT_NODE`](crate::parser::DOCUMENT_NODE).
+    pub fn subtree(&mut self, _cx: CX![], node: NodeId, callback: Ret<DomSubtree>) {
+        if let Some(subtree) = self.tree.subtree(node) {
+            ret!([callback], subtree);
+        }
     }
 
-    pub fn set_previous_sibling(&mut self, cx: CX![], other: Option<Actor<DomEntry>>) {
-        self.previous_sibling = other;
+    /// Serialize `node` and its descendants to HTML.
+    pub fn outer_html(&mut self, _cx: CX![], node: NodeId, callback: Ret<String>) {
+        self.answer(node, callback, |entry| {
+            serializer::outer_html(&self.tree, entry)
+        });
     }
-    pub fn previous_sibling(&mut self, cx: CX![], callback: Ret<Option<Actor<DomEntry>>>) {
-        ret!([callback], self.previous_sibling.clone());
+
+    /// Serialize the descendants of `node` to HTML.
+    pub fn inner_html(&mut self, _cx: CX![], node: NodeId, callback: Ret<String>) {
+        self.answer(node, callback, |entry| {
+            serializer::inner_html(&self.tree, entry)
+        });
     }
 
-    pub fn set_next_sibling(&mut self, cx: CX![], other: Option<Actor<DomEntry>>) {
-        self.next_sibling = other;
+    /// The text of `node` and everything under it, as for `textContent`.
+    pub fn text_content(&mut self, _cx: CX![], node: NodeId, callback: Ret<EcoString>) {
+        if self.tree.contains(node) {
+            ret!([callback], self.tree.text_content(node).into());
+        }
     }
-    pub fn next_sibling(&mut self, cx: CX![], callback: Ret<Option<Actor<DomEntry>>>) {
-        ret!([callback], self.next_sibling.clone());
+
+    /// Append `new_suffix` to the text node `node`, as for `appendData`. Other nodes have no
+    /// text of their own to extend, so they are left as they are.
+    pub fn append_text_content(&mut self, cx: CX![], node: NodeId, new_suffix: EcoString) {
+        let Some(entry) = self.tree.get_mut(node) else {
+            return;
+        };
+        if let Some(old_value) = entry.myself.text_contents() {
+            entry.myself.append_text_content(&new_suffix);
+            let record = MutationRecord {
+                target: node,
+                kind: MutationKind::CharacterData { old_value },
+            };
+            self.queue_mutation(cx, record);
+        }
     }
 
-    pub fn first_child(&mut self, cx: CX![], callback: Ret<Option<Actor<DomEntry>>>) {
-        ret!([callback], self.first_child.clone());
+    pub fn debug(&mut self, _cx: CX![], node: NodeId) {
+        if let Some(entry) = self.tree.get(node) {
+            entry.debug();
+        }
     }
+}
 
-    fn set_first_child(&mut self, cx: CX![], child: Option<Actor<DomEntry>>) {
-        self.first_child = child;
+/// Edits to the tree, each calling back once the edit has been made or refused. Detached nodes are
+/// released at the end of the turn unless they are retained or put back by then, and edits
+/// involving a released node fail with [`DomError::NotFound`].
+impl MjDom {
+    fn all_present(&self, nodes: impl IntoIterator<Item = NodeId>) -> Result<(), DomError> {
+        let present = nodes.into_iter().all(|node| self.tree.contains(node));
+        present.then_some(()).ok_or(DomError::NotFound)
     }
 
-    pub fn last_child(&mut self, cx: CX![], callback: Ret<Option<Actor<DomEntry>>>) {
-        ret!([callback], self.last_child.clone());
+    /// Move `child` to the end of the children of `parent`, taking it away from wherever it was.
+    pub fn append(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        child: NodeId,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        self.insert_before(cx, parent, child, None, callback);
     }
 
-    fn set_last_child(&mut self, cx: CX![], child: Option<Actor<DomEntry>>) {
-        self.first_child = child;
+    /// Move `child` in front of `reference`, which has to be one of the children of `parent`, or
+    /// to the end if there is no reference.
+    pub fn insert_before(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        child: NodeId,
+        reference: Option<NodeId>,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        let result = self
+            .all_present([parent, child].into_iter().chain(reference))
+            .and_then(|()| self.insert_node(cx, parent, child, reference));
+        ret!([callback], result);
     }
 
-    pub fn id(&mut self, cx: CX![], callback: Ret<NodeId>) {
-        ret!([callback], self.id);
+    /// Detach `child`, failing with [`DomError::NotFound`] if it isn't one of the children of
+    /// `parent`.
+    pub fn remove_child(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        child: NodeId,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        let result = self
+            .all_present([parent, child])
+            .and_then(|()| self.remove_node(cx, Some(parent), child));
+        ret!([callback], result);
     }
 
-    pub fn text_content(&mut self, cx: CX![], callback: Ret<EcoString>) {
-        // Todo: call recursively if we're not a text content element
-        if self.myself.is_text() {
-            ret!([callback], self.myself.text_contents().unwrap())
-        }
+    /// Put `new_child` where `old_child` is among the children of `parent`, detaching `old_child`.
+    pub fn replace_child(
+        &mut self,
+        cx: CX![],
+        parent: NodeId,
+        new_child: NodeId,
+        old_child: NodeId,
+        callback: Ret<Result<(), DomError>>,
+    ) {
+        let result = self
+            .all_present([parent, new_child, old_child])
+            .and_then(|()| self.replace_node(cx, parent, new_child, old_child));
+        ret!([callback], result);
     }
 
-    pub fn append_text_content(&mut self, cx: CX![], new_suffix: EcoString) {
-        // Todo: Wipe children if we're not a text node
-        if self.myself.is_text() {
-            self.myself.append_text_content(&new_suffix)
-        }
+    /// Detach `node` from its parent, if it has one.
+    pub fn remove(&mut self, cx: CX![], node: NodeId, callback: Ret<Result<(), DomError>>) {
+        let result = self
+            .all_present([node])
+            .and_then(|()| self.remove_node(cx, None, node));
+        ret!([callback], result);
     }
 }
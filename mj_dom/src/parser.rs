@@ -1,45 +1,365 @@
-use std::{
-    borrow::Cow,
-    collections::VecDeque,
-    io::BufReader,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use std::{borrow::Cow, collections::VecDeque};
 
-use hashbrown::HashMap;
+use ecow::EcoString;
+use encoding_rs::{Decoder, Encoding};
 use html5ever::{
+    driver::{parse_fragment_for_element, Parser},
     interface::{
         ElementFlags,
         NodeOrText::{self, AppendNode, AppendText},
         QuirksMode, TreeSink,
     },
-    parse_document,
-    tendril::{StrTendril, TendrilSink},
-    Attribute, ExpandedName, QualName,
+    namespace_url, ns, parse_document,
+    tendril::{fmt::UTF8, StrTendril, TendrilSink},
+    tree_builder::TreeBuilderOpts,
+    Attribute, ExpandedName, LocalName, ParseOpts, QualName,
 };
 use stakker::PipedLink;
-use vello::glyph::skrifa::attribute;
+use url::Url;
+use xml5ever::driver::{parse_document as parse_xml_document, XmlParser};
+
+use crate::{
+    diagnostics::ParseDiagnostic,
+    encoding::{sniff_encoding, sniff_xml_encoding, PRESCAN_LENGTH},
+    shadow::{is_valid_shadow_host, ShadowRootInit, ShadowRootMode},
+};
 
-use crate::nodes::MemberKind;
+/// Identifies a node of the current document, and is how every [`MjDom`](crate::MjDom) message
+/// refers to one.
+pub type NodeId = usize;
+/// Ids are allocated per document, and the document itself always takes the first.
+pub const DOCUMENT_NODE: NodeId = 0;
+
+/// How many operations the parser collects before sending them to the DOM, unless it reaches the
+/// end of a chunk first. Large enough that the message overhead vanishes, small enough that a
+/// streaming document still shows up a piece at a time.
+const BATCH_SIZE: usize = 1024;
+
+/// A document as it arrives from the network, fed to the parser a piece at a time.
+#[derive(Clone, Debug)]
+pub enum DocumentChunk {
+    /// A new document begins, served with this `Content-Type` if the transport gave one, from
+    /// `url` if it was fetched from anywhere.
+    Start {
+        content_type: Option<String>,
+        url: Option<Url>,
+    },
+    Data(Vec<u8>),
+    End,
+}
 
-pub(crate) type NodeId = usize;
-static NEXT_NODE_ID: AtomicUsize = AtomicUsize::new(0);
+/// Whether a document served as `content_type` is XML, to be parsed with the XML parser rather
+/// than the HTML one. Besides the XML types themselves, that covers XHTML, SVG and anything else
+/// with a `+xml` suffix.
+pub fn is_xml_content_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let essence = essence.to_ascii_lowercase();
+    matches!(essence.as_str(), "application/xml" | "text/xml")
+        || (essence.ends_with("+xml") && essence.contains('/'))
+}
 
+/// A request to parse `html` into the new children of the element `context`, the way assigning
+/// `innerHTML` does.
 #[derive(Clone, Debug)]
-pub struct ParserNodeElement {
-    pub id: NodeId,
-    pub name: Option<QualName>,
+pub(crate) struct FragmentRequest {
+    pub context: NodeId,
+    pub context_name: QualName,
+    /// The quirks mode of the document the fragment is going into.
+    pub quirks_mode: QuirksMode,
+    pub html: String,
+}
+
+pub(crate) enum ParserInput {
+    Chunk(DocumentChunk),
+    Fragment(FragmentRequest),
+    /// A shadow root to be attached to `host`, which only needs a node id from the parser.
+    AttachShadow {
+        host: NodeId,
+        init: ShadowRootInit,
+    },
+}
+
+/// Decode and parse each document streamed down `link`, and each fragment requested for it,
+/// until the DOM hangs up.
+pub(crate) fn run(link: &mut PipedLink<ParserInput, Vec<ParseOperation>>) {
+    // Fragment nodes are allocated from the same ids as the document they go into
+    let mut next_node_id = DOCUMENT_NODE + 1;
+    let mut deferred = VecDeque::new();
+    let mut next = link.recv();
+    while let Some(input) = next {
+        let interrupted = match input {
+            ParserInput::Chunk(DocumentChunk::Start { content_type, .. }) => {
+                parse_stream(link, content_type, &mut next_node_id, &mut deferred)
+            }
+            // Anything outside of a Start and End pair belongs to an abandoned document
+            ParserInput::Chunk(_) => None,
+            request => {
+                deferred.push_back(request);
+                None
+            }
+        };
+        for request in deferred.drain(..) {
+            match request {
+                ParserInput::Fragment(request) => parse_fragment(link, request, &mut next_node_id),
+                ParserInput::AttachShadow { host, init } => {
+                    let root = next_node_id;
+                    next_node_id += 1;
+                    link.send(vec![ParseOperation::AttachShadow {
+                        host,
+                        root,
+                        template: None,
+                        init,
+                    }]);
+                }
+                ParserInput::Chunk(_) => {}
+            }
+        }
+        next = interrupted.or_else(|| link.recv());
+    }
+}
+
+/// Parse one document as its chunks arrive. Fragments and shadow roots requested in the meantime
+/// are queued into `deferred`, since they can only be dealt with once the document is complete,
+/// with every node id it needs handed out. A new document starting before this one ends abandons
+/// it, and is handed back to [`run`].
+fn parse_stream(
+    link: &mut PipedLink<ParserInput, Vec<ParseOperation>>,
+    content_type: Option<String>,
+    next_node_id: &mut NodeId,
+    deferred: &mut VecDeque<ParserInput>,
+) -> Option<ParserInput> {
+    // Sent straight away, so that the DOM knows to drop whatever is still on its way about the
+    // previous document even if this one is abandoned before anything else is sent
+    link.send(vec![ParseOperation::Begin]);
+
+    // The encoding has to be settled before anything is decoded, which takes enough of the
+    // document for the meta prescan or the XML declaration
+    let mut prefix = Vec::new();
+    let mut ended = false;
+    while prefix.len() < PRESCAN_LENGTH && !ended {
+        match link.recv() {
+            Some(ParserInput::Chunk(DocumentChunk::Data(bytes))) => prefix.extend(bytes),
+            Some(ParserInput::Chunk(DocumentChunk::End)) => ended = true,
+            Some(ParserInput::Chunk(chunk)) => return Some(ParserInput::Chunk(chunk)),
+            Some(request) => deferred.push_back(request),
+            None => return None,
+        }
+    }
+
+    let xml = content_type.as_deref().is_some_and(is_xml_content_type);
+    let encoding = if xml {
+        sniff_xml_encoding(&prefix, content_type.as_deref())
+    } else {
+        sniff_encoding(&prefix, content_type.as_deref())
+    };
+    let mut sink = MjDomParser::new(link);
+    sink.xml = xml;
+    sink.send(ParseOperation::SetEncoding { encoding });
+    let decoder = encoding.new_decoder();
+    let stream = Stream {
+        decoder,
+        prefix,
+        ended,
+        next_node_id,
+        deferred,
+    };
+    if xml {
+        stream.parse(parse_xml_document(sink, Default::default()))
+    } else {
+        stream.parse(parse_document(sink, Default::default()))
+    }
+}
+
+/// A document whose encoding has been settled, ready to go through either parser.
+struct Stream<'a> {
+    decoder: Decoder,
+    /// What arrived while the encoding was being sniffed.
+    prefix: Vec<u8>,
+    ended: bool,
+    next_node_id: &'a mut NodeId,
+    deferred: &'a mut VecDeque<ParserInput>,
+}
+
+impl Stream<'_> {
+    fn parse<'parser>(mut self, mut parser: impl DocumentParser<'parser>) -> Option<ParserInput> {
+        parser.begin(decode(&mut self.decoder, &self.prefix, self.ended));
+        // Whatever the chunk added is sent before waiting on the next one
+        parser.sink().flush();
+
+        while !self.ended {
+            match parser.sink().link.recv() {
+                Some(ParserInput::Chunk(DocumentChunk::Data(bytes))) => {
+                    parser.feed(decode(&mut self.decoder, &bytes, false));
+                    parser.sink().flush();
+                }
+                Some(ParserInput::Chunk(DocumentChunk::End)) => {
+                    parser.feed(decode(&mut self.decoder, &[], true));
+                    self.ended = true;
+                }
+                Some(ParserInput::Chunk(chunk)) => return Some(ParserInput::Chunk(chunk)),
+                Some(request) => self.deferred.push_back(request),
+                None => return None,
+            }
+        }
+        *self.next_node_id = parser.finish().next_node_id;
+        None
+    }
+}
+
+/// What streaming a document needs from html5ever's and xml5ever's parsers alike.
+trait DocumentParser<'parser>: TendrilSink<UTF8, Output = MjDomParser<'parser>> {
+    fn sink(&mut self) -> &mut MjDomParser<'parser>;
+
+    /// Feed the first of the document, which the encoding was sniffed from.
+    fn begin(&mut self, text: StrTendril) {
+        self.feed(text);
+    }
+
+    fn feed(&mut self, text: StrTendril) {
+        self.process(text);
+    }
+}
+
+impl<'parser> DocumentParser<'parser> for Parser<MjDomParser<'parser>> {
+    fn sink(&mut self) -> &mut MjDomParser<'parser> {
+        &mut self.tokenizer.sink.sink
+    }
+}
+
+impl<'parser> DocumentParser<'parser> for XmlParser<MjDomParser<'parser>> {
+    fn sink(&mut self) -> &mut MjDomParser<'parser> {
+        &mut self.tokenizer.sink.sink
+    }
+
+    /// The XML declaration only names the encoding, which has been settled by now, and is not
+    /// part of the tree. xml5ever would otherwise make a processing instruction of it.
+    fn begin(&mut self, mut text: StrTendril) {
+        let declaration = text.strip_prefix("<?xml");
+        if declaration.is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_whitespace())) {
+            if let Some(end) = text.find("?>") {
+                text.pop_front(end as u32 + 2);
+            }
+        }
+        self.feed(text);
+    }
+
+    /// xml5ever doesn't count lines the way html5ever does, so the text is fed a line at a time
+    /// to keep track of them instead.
+    fn feed(&mut self, mut text: StrTendril) {
+        while let Some(end) = text.find('\n') {
+            let line = text.subtendril(0, end as u32 + 1);
+            text.pop_front(end as u32 + 1);
+            self.process(line);
+            let sink = self.sink();
+            sink.set_current_line(sink.current_line + 1);
+        }
+        if !text.is_empty() {
+            self.process(text);
+        }
+    }
+}
+
+/// Parse a fragment with html5ever's fragment parsing algorithm. The fragment is built under a
+/// root of its own, and the DOM moves the result into the context element once it's finished.
+fn parse_fragment(
+    link: &mut PipedLink<ParserInput, Vec<ParseOperation>>,
+    request: FragmentRequest,
+    next_node_id: &mut NodeId,
+) {
+    let root = *next_node_id;
+    let mut sink = MjDomParser::with_root(link, root, Some(request.context));
+    sink.send(ParseOperation::BeginFragment { root });
+    // The context element already exists in the DOM. The parser only asks about it and never
+    // inserts into it, so it doesn't need creating
+    sink.insert_entry(request.context, Some(request.context_name), false);
+
+    let opts = ParseOpts {
+        tree_builder: TreeBuilderOpts {
+            quirks_mode: request.quirks_mode,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let sink = parse_fragment_for_element(sink, opts, request.context, None).one(request.html);
+    *next_node_id = sink.next_node_id;
+}
+
+/// The shadow root a template with these attributes declares, if its `shadowrootmode` is valid.
+fn declared_shadow_root(attributes: &[Attribute]) -> Option<ShadowRootInit> {
+    let attribute = |name: &str| {
+        attributes
+            .iter()
+            .find(|attr| attr.name.ns.is_empty() && &*attr.name.local == name)
+    };
+    let mode = ShadowRootMode::from_attribute(&attribute("shadowrootmode")?.value)?;
+    Some(ShadowRootInit {
+        mode,
+        delegates_focus: attribute("shadowrootdelegatesfocus").is_some(),
+        clonable: attribute("shadowrootclonable").is_some(),
+        serializable: attribute("shadowrootserializable").is_some(),
+    })
+}
+
+/// Decode the next piece of a document. A multi-byte sequence split across chunks is held back
+/// by `decoder` until the rest of it arrives.
+fn decode(decoder: &mut Decoder, bytes: &[u8], last: bool) -> StrTendril {
+    let capacity = decoder
+        .max_utf8_buffer_length(bytes.len())
+        .expect("Chunk too large to decode");
+    let mut text = String::with_capacity(capacity);
+    let (_, read, _) = decoder.decode_to_string(bytes, &mut text, last);
+    debug_assert_eq!(read, bytes.len());
+    StrTendril::from(text)
 }
 
 #[derive(Clone, Debug)]
 pub struct ParserAttribute {
-    name: QualName,
-    value: String,
+    pub name: QualName,
+    pub value: EcoString,
 }
 
 #[derive(Clone, Debug)]
 pub enum ParserNodeOrText {
-    Node(ParserNodeElement),
-    Text(NodeId, String),
+    Node(NodeId),
+    /// A run of text. If the id belongs to a text node that was already created, the run is
+    /// appended to that node rather than inserted, since html5ever expects adjacent text to merge.
+    Text(NodeId, EcoString),
+}
+
+/// The slot a node occupies in the tree, as resolved by the parser when the operation was emitted.
+/// `previous` and `next` are the siblings on either side, `None` meaning the node is the first or
+/// last child of `parent`.
+#[derive(Clone, Debug)]
+pub struct ParserPosition {
+    pub parent: NodeId,
+    pub previous: Option<NodeId>,
+    pub next: Option<NodeId>,
+}
+
+/// The parser's own view of the tree structure. html5ever asks the sink structural questions
+/// synchronously (e.g. whether a node has a parent), so the parser tracks the links itself and
+/// sends fully resolved positions across the thread.
+#[derive(Clone, Debug, Default)]
+struct ParserLinks {
+    parent: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+#[derive(Clone, Debug)]
+struct ParserEntry {
+    /// The element's name, which the tree builder keeps asking about.
+    name: Option<QualName>,
+    links: ParserLinks,
+    is_text: bool,
+    mathml_annotation_xml_integration_point: bool,
+    template_contents: Option<NodeId>,
+    /// For a `<template shadowrootmode>`, the shadow root its contents are to become for the
+    /// element it is inserted into.
+    declared_shadow_root: Option<ShadowRootInit>,
+    is_shadow_host: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -49,6 +369,15 @@ pub enum ParseOperation {
         contents: NodeId,
     },
 
+    /// Make `root` the shadow root of `host`. A declarative shadow root is the contents of
+    /// `template`, which is never inserted anywhere, and any other is a new fragment.
+    AttachShadow {
+        host: NodeId,
+        root: NodeId,
+        template: Option<NodeId>,
+        init: ShadowRootInit,
+    },
+
     CreateElement {
         node: NodeId,
         name: QualName,
@@ -57,27 +386,32 @@ pub enum ParseOperation {
     },
 
     CreateComment {
-        text: String,
+        text: EcoString,
         node: NodeId,
     },
     AppendBeforeSibling {
         sibling: NodeId,
         node: ParserNodeOrText,
+        position: ParserPosition,
     },
     AppendBasedOnParentNode {
         element: NodeId,
         prev_element: NodeId,
         node: ParserNodeOrText,
+        position: ParserPosition,
     },
     Append {
         parent: NodeId,
         node: ParserNodeOrText,
+        position: ParserPosition,
     },
 
     AppendDoctypeToDocument {
-        name: String,
-        public_id: String,
-        system_id: String,
+        node: NodeId,
+        name: EcoString,
+        public_id: EcoString,
+        system_id: EcoString,
+        position: ParserPosition,
     },
 
     AddAttrsIfMissing {
@@ -86,6 +420,7 @@ pub enum ParseOperation {
     },
     RemoveFromParent {
         target: NodeId,
+        position: ParserPosition,
     },
     MarkScriptAlreadyStarted {
         node: NodeId,
@@ -93,19 +428,19 @@ pub enum ParseOperation {
     ReparentChildren {
         parent: NodeId,
         new_parent: NodeId,
+        children: Vec<NodeId>,
     },
 
+    /// `target` was parsed inside `form`, which makes it the target's form owner.
     AssociateWithForm {
         target: NodeId,
         form: NodeId,
-        element: NodeId,
-        prev_element: Option<NodeId>,
     },
 
     CreatePI {
         node: NodeId,
-        target: String,
-        data: String,
+        target: EcoString,
+        data: EcoString,
     },
 
     Pop {
@@ -113,190 +448,506 @@ pub enum ParseOperation {
     },
 
     SetQuirksMode {
-        mode: String,
+        mode: QuirksMode,
+    },
+
+    SetEncoding {
+        encoding: &'static Encoding,
+    },
+
+    SetCurrentLine {
+        line: u64,
+    },
+
+    ParseError {
+        message: String,
+        line: u64,
+    },
+
+    /// Everything after this belongs to the document from the latest [`DocumentChunk::Start`]
+    /// the parser has seen.
+    Begin,
+
+    /// A fragment is about to be parsed under `root`, a fragment of its own.
+    BeginFragment {
+        root: NodeId,
+    },
+
+    Finish,
+
+    /// The fragment under `root` is complete. `children` are the nodes that replace the children
+    /// of `context`, after which `root` and `html`, the element html5ever parses fragments into,
+    /// are no longer needed.
+    FinishFragment {
+        context: NodeId,
+        root: NodeId,
+        html: NodeId,
+        children: Vec<NodeId>,
     },
 }
 
 pub struct MjDomParser<'parser> {
     document_node: NodeId,
-    entries: HashMap<NodeId, ParserNodeElement>,
+    next_node_id: NodeId,
+    /// The element being parsed into, when parsing a fragment rather than a document.
+    fragment_context: Option<NodeId>,
+    current_line: u64,
+    /// The entries of every node created since `document_node`, indexed by their distance from it.
+    entries: Vec<ParserEntry>,
+    /// A fragment's context element, which comes from the document rather than this parser.
+    context_entry: Option<ParserEntry>,
+    /// Operations waiting to be sent as one batch.
+    pending: Vec<ParseOperation>,
+    /// Whether this is an XML document, which is replaced by an error message if it isn't
+    /// well-formed.
+    xml: bool,
+    first_error: Option<ParseDiagnostic>,
 
-    link: &'parser mut PipedLink<String, ParseOperation>,
+    link: &'parser mut PipedLink<ParserInput, Vec<ParseOperation>>,
 }
 
 impl<'parser> MjDomParser<'parser> {
-    pub fn new(link: &'parser mut PipedLink<String, ParseOperation>) -> Self {
+    pub(crate) fn new(link: &'parser mut PipedLink<ParserInput, Vec<ParseOperation>>) -> Self {
+        Self::with_root(link, DOCUMENT_NODE, None)
+    }
+
+    fn with_root(
+        link: &'parser mut PipedLink<ParserInput, Vec<ParseOperation>>,
+        root: NodeId,
+        fragment_context: Option<NodeId>,
+    ) -> Self {
         let mut parser = Self {
             link,
-            document_node: 0,
-            entries: HashMap::new(),
+            document_node: root,
+            next_node_id: root + 1,
+            fragment_context,
+            current_line: 1,
+            entries: vec![],
+            context_entry: None,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            xml: false,
+            first_error: None,
         };
-        let root_id = parser.add_root();
-        parser.document_node = root_id;
+        parser.insert_entry(root, None, false);
         parser
     }
 
-    fn add_root(&mut self) -> NodeId {
-        let node_id = NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst);
-        self.entries.insert(
-            node_id,
-            ParserNodeElement {
-                id: node_id,
-                name: None,
-            },
-        );
+    /// Queue `operation` for the DOM, sending the batch once it is full.
+    fn send(&mut self, operation: ParseOperation) {
+        self.pending.push(operation);
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    /// Send whatever operations are waiting.
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let batch = std::mem::replace(&mut self.pending, Vec::with_capacity(BATCH_SIZE));
+            self.link.send(batch);
+        }
+    }
+
+    fn add_entry(&mut self, name: Option<QualName>, is_text: bool) -> NodeId {
+        let node_id = self.next_node_id;
+        self.next_node_id += 1;
+        self.insert_entry(node_id, name, is_text);
         node_id
     }
 
+    fn insert_entry(&mut self, node_id: NodeId, name: Option<QualName>, is_text: bool) {
+        let entry = ParserEntry {
+            name,
+            links: ParserLinks::default(),
+            is_text,
+            mathml_annotation_xml_integration_point: false,
+            template_contents: None,
+            declared_shadow_root: None,
+            is_shadow_host: false,
+        };
+        if Some(node_id) == self.fragment_context {
+            self.context_entry = Some(entry);
+        } else {
+            debug_assert_eq!(node_id, self.document_node + self.entries.len());
+            self.entries.push(entry);
+        }
+    }
+
     fn add_element(&mut self, name: QualName) -> NodeId {
-        let node_id = NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst);
-        self.entries.insert(
-            node_id,
-            ParserNodeElement {
-                id: node_id,
-                name: Some(name),
-            },
-        );
-        node_id
+        self.add_entry(Some(name), false)
     }
 
     fn add_text(&mut self) -> NodeId {
-        let node_id = NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst);
-        self.entries.insert(
-            node_id,
-            ParserNodeElement {
-                id: node_id,
-                name: None,
-            },
-        );
-        node_id
+        self.add_entry(None, true)
     }
 
     pub fn add_comment(&mut self) -> NodeId {
-        let node_id = NEXT_NODE_ID.fetch_add(1, Ordering::SeqCst);
-        self.entries.insert(
-            node_id,
-            ParserNodeElement {
-                id: node_id,
-                name: None,
-            },
-        );
-        node_id
+        self.add_entry(None, false)
+    }
+
+    fn entry(&self, node_id: NodeId) -> &ParserEntry {
+        let entry = match node_id.checked_sub(self.document_node) {
+            Some(index) => self.entries.get(index),
+            None => self.context_entry.as_ref(),
+        };
+        entry.expect("Could not find expected member")
+    }
+
+    fn entry_mut(&mut self, node_id: NodeId) -> &mut ParserEntry {
+        let entry = match node_id.checked_sub(self.document_node) {
+            Some(index) => self.entries.get_mut(index),
+            None => self.context_entry.as_mut(),
+        };
+        entry.expect("Could not find expected member")
+    }
+
+    fn links(&self, node_id: NodeId) -> &ParserLinks {
+        &self.entry(node_id).links
     }
 
-    fn node(&self, node_id: NodeId) -> &ParserNodeElement {
-        self.entries
-            .get(&node_id)
-            .expect("Could not find expected member")
+    /// Make the contents of `template` the shadow root of `host` instead of inserting the
+    /// template there, if it declares a shadow root and `host` can have it. Otherwise it goes
+    /// in as an ordinary template.
+    fn attach_declarative_shadow(&mut self, host: NodeId, template: NodeId) -> bool {
+        let Some(init) = self.entry_mut(template).declared_shadow_root.take() else {
+            return false;
+        };
+        let host_entry = self.entry(host);
+        let can_host = host_entry.name.as_ref().is_some_and(is_valid_shadow_host);
+        if !can_host || host_entry.is_shadow_host {
+            return false;
+        }
+        self.entry_mut(host).is_shadow_host = true;
+        let root = self
+            .entry(template)
+            .template_contents
+            .expect("Only template elements declare shadow roots");
+        self.send(ParseOperation::AttachShadow {
+            host,
+            root,
+            template: Some(template),
+            init,
+        });
+        true
+    }
+
+    /// Replace everything in the document with a message about `error`, as XML that isn't
+    /// well-formed isn't shown at all. The new nodes go through the usual operations, so their
+    /// ids are handed out like any others.
+    fn show_xml_error(&mut self, error: &ParseDiagnostic) {
+        let document = self.document_node;
+        while let Some(child) = self.links(document).first_child {
+            if let Some(position) = self.unlink_node(child) {
+                self.send(ParseOperation::RemoveFromParent {
+                    target: child,
+                    position,
+                });
+            }
+        }
+        let mut parent = document;
+        for local in ["html", "body", "parsererror"] {
+            let name = QualName::new(None, ns!(html), LocalName::from(local));
+            let element = self.create_element(name, vec![], ElementFlags::default());
+            self.append(&parent, AppendNode(element));
+            parent = element;
+        }
+        let message = format!("XML parsing error on {}", error);
+        self.append(&parent, AppendText(StrTendril::from(message)));
+    }
+
+    /// The top of the tree `node` is in, as far as the parser has built it.
+    fn root(&self, mut node: NodeId) -> NodeId {
+        while let Some(parent) = self.links(node).parent {
+            node = parent;
+        }
+        node
+    }
+
+    /// Link `node` into `parent` directly before `next`, or as the last child if `next` is `None`.
+    fn link_node(&mut self, node: NodeId, parent: NodeId, next: Option<NodeId>) -> ParserPosition {
+        let previous = match next {
+            Some(next) => self.links(next).previous_sibling,
+            None => self.links(parent).last_child,
+        };
+
+        match previous {
+            Some(previous) => self.entry_mut(previous).links.next_sibling = Some(node),
+            None => self.entry_mut(parent).links.first_child = Some(node),
+        }
+        match next {
+            Some(next) => self.entry_mut(next).links.previous_sibling = Some(node),
+            None => self.entry_mut(parent).links.last_child = Some(node),
+        }
+
+        let links = &mut self.entry_mut(node).links;
+        links.parent = Some(parent);
+        links.previous_sibling = previous;
+        links.next_sibling = next;
+
+        ParserPosition {
+            parent,
+            previous,
+            next,
+        }
+    }
+
+    /// Unlink `node` from its parent, returning the position it was removed from.
+    fn unlink_node(&mut self, node: NodeId) -> Option<ParserPosition> {
+        let links = self.links(node).clone();
+        let parent = links.parent?;
+
+        match links.previous_sibling {
+            Some(previous) => self.entry_mut(previous).links.next_sibling = links.next_sibling,
+            None => self.entry_mut(parent).links.first_child = links.next_sibling,
+        }
+        match links.next_sibling {
+            Some(next) => self.entry_mut(next).links.previous_sibling = links.previous_sibling,
+            None => self.entry_mut(parent).links.last_child = links.previous_sibling,
+        }
+
+        let own = &mut self.entry_mut(node).links;
+        own.parent = None;
+        own.previous_sibling = None;
+        own.next_sibling = None;
+
+        Some(ParserPosition {
+            parent,
+            previous: links.previous_sibling,
+            next: links.next_sibling,
+        })
+    }
+
+    /// Resolve a tree-builder insertion of `child` into `parent` before `next`. Nodes that are
+    /// still attached elsewhere are removed from their old parent first, and text that lands next
+    /// to an existing text node is merged into it.
+    fn insert(
+        &mut self,
+        parent: NodeId,
+        next: Option<NodeId>,
+        child: NodeOrText<NodeId>,
+    ) -> (ParserNodeOrText, ParserPosition) {
+        match child {
+            AppendNode(node) => {
+                if let Some(position) = self.unlink_node(node) {
+                    self.send(ParseOperation::RemoveFromParent {
+                        target: node,
+                        position,
+                    });
+                }
+                let position = self.link_node(node, parent, next);
+                (ParserNodeOrText::Node(node), position)
+            }
+            AppendText(content) => {
+                let previous = match next {
+                    Some(next) => self.links(next).previous_sibling,
+                    None => self.links(parent).last_child,
+                };
+                let content = EcoString::from(&*content);
+                match previous.filter(|previous| self.entry(*previous).is_text) {
+                    Some(text_node) => (
+                        ParserNodeOrText::Text(text_node, content),
+                        ParserPosition {
+                            parent,
+                            previous: self.links(text_node).previous_sibling,
+                            next,
+                        },
+                    ),
+                    None => {
+                        let node_id = self.add_text();
+                        let position = self.link_node(node_id, parent, next);
+                        (ParserNodeOrText::Text(node_id, content), position)
+                    }
+                }
+            }
+        }
     }
 }
 
-impl<'parse_context, 'parser: 'parse_context> TreeSink for MjDomParser<'parser> {
-    type Handle = ParserNodeElement;
+impl<'parser> TreeSink for MjDomParser<'parser> {
+    type Handle = NodeId;
     type Output = Self;
 
-    fn finish(self) -> Self::Output {
+    fn finish(mut self) -> Self::Output {
+        let Some(context) = self.fragment_context else {
+            if let Some(error) = self.first_error.take().filter(|_| self.xml) {
+                self.show_xml_error(&error);
+            }
+            self.send(ParseOperation::Finish);
+            self.flush();
+            return self;
+        };
+        let html = self
+            .links(self.document_node)
+            .first_child
+            .expect("Fragments are parsed into an html element");
+        let mut children = vec![];
+        let mut child = self.links(html).first_child;
+        while let Some(id) = child {
+            children.push(id);
+            child = self.links(id).next_sibling;
+        }
+        self.send(ParseOperation::FinishFragment {
+            context,
+            root: self.document_node,
+            html,
+            children,
+        });
+        self.flush();
         self
     }
 
     fn parse_error(&mut self, msg: Cow<'static, str>) {
-        println!("Parse error: {}", msg);
+        if self.xml && self.first_error.is_none() {
+            self.first_error = Some(ParseDiagnostic {
+                line: self.current_line,
+                column: None,
+                message: EcoString::from(&*msg),
+            });
+        }
+        self.send(ParseOperation::ParseError {
+            message: msg.into_owned(),
+            line: self.current_line,
+        });
     }
 
     fn get_document(&mut self) -> Self::Handle {
-        Self::Handle {
-            id: self.document_node,
-            name: None,
-        }
+        self.document_node
     }
 
     fn get_template_contents(&mut self, target: &Self::Handle) -> Self::Handle {
-        todo!()
+        self.entry(*target)
+            .template_contents
+            .expect("Only template elements have contents")
     }
 
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
-        println!("Set quirks mode to {:?}", mode);
+        // A fragment takes the mode of the document it goes into
+        if self.fragment_context.is_none() {
+            self.send(ParseOperation::SetQuirksMode { mode });
+        }
     }
 
     fn same_node(&self, x: &Self::Handle, y: &Self::Handle) -> bool {
-        x.id == y.id
+        x == y
     }
 
     fn elem_name<'a>(&'a self, target: &'a Self::Handle) -> ExpandedName<'a> {
-        self.node(target.id)
+        self.entry(*target)
             .name
             .as_ref()
             .expect("Could not get name of node")
             .expanded()
     }
 
+    fn is_mathml_annotation_xml_integration_point(&self, handle: &Self::Handle) -> bool {
+        self.entry(*handle).mathml_annotation_xml_integration_point
+    }
+
     fn create_element(
         &mut self,
         name: QualName,
         attributes: Vec<Attribute>,
-        _flags: ElementFlags,
+        flags: ElementFlags,
     ) -> Self::Handle {
         let node_id = self.add_element(name.clone());
+        self.entry_mut(node_id)
+            .mathml_annotation_xml_integration_point =
+            flags.mathml_annotation_xml_integration_point;
 
         let attrs = attributes
             .iter()
             .map(|attr| ParserAttribute {
                 name: attr.name.clone(),
-                value: attr.value.to_string(),
+                value: EcoString::from(&*attr.value),
             })
             .collect();
-        self.link.send(ParseOperation::CreateElement {
+        self.send(ParseOperation::CreateElement {
             node: node_id,
-            name: name.clone(),
+            name,
             attrs,
-            current_line: 1,
+            current_line: self.current_line,
         });
 
-        Self::Handle {
-            id: node_id,
-            name: Some(name),
+        // The contents fragment is created along with the template, so every template has one
+        // whether or not anything is ever parsed into it
+        if flags.template {
+            let contents = self.add_entry(None, false);
+            self.entry_mut(node_id).template_contents = Some(contents);
+            // Markup set as inner HTML can't declare shadow roots, only a whole document can
+            if self.fragment_context.is_none() {
+                self.entry_mut(node_id).declared_shadow_root = declared_shadow_root(&attributes);
+            }
+            self.send(ParseOperation::GetTemplateContents {
+                target: node_id,
+                contents,
+            });
         }
+
+        node_id
     }
 
     fn create_comment(&mut self, text: StrTendril) -> Self::Handle {
         let node_id = self.add_comment();
-        self.link.send(ParseOperation::CreateComment {
+        self.send(ParseOperation::CreateComment {
             node: node_id,
-            text: String::from(text),
+            text: EcoString::from(&*text),
         });
-        Self::Handle {
-            id: node_id,
-            name: None,
-        }
+        node_id
     }
 
-    #[allow(unused_variables)]
     fn create_pi(&mut self, target: StrTendril, value: StrTendril) -> Self::Handle {
-        todo!()
+        let node_id = self.add_entry(None, false);
+        self.send(ParseOperation::CreatePI {
+            node: node_id,
+            target: EcoString::from(&*target),
+            data: EcoString::from(&*value),
+        });
+        node_id
     }
 
     fn append(&mut self, parent: &Self::Handle, child: NodeOrText<Self::Handle>) {
-        let node_id = self.add_text();
-        self.link.send(ParseOperation::Append {
-            parent: parent.id,
-            node: match child {
-                AppendNode(node) => ParserNodeOrText::Node(node),
-                AppendText(content) => {
-                    let content = String::from(content);
-                    ParserNodeOrText::Text(node_id, content)
-                }
-            },
+        if let AppendNode(node) = &child {
+            if self.attach_declarative_shadow(*parent, *node) {
+                return;
+            }
+        }
+        let (node, position) = self.insert(*parent, None, child);
+        self.send(ParseOperation::Append {
+            parent: *parent,
+            node,
+            position,
         });
     }
 
-    fn append_before_sibling(&mut self, sibling: &Self::Handle, child: NodeOrText<Self::Handle>) {}
+    fn append_before_sibling(&mut self, sibling: &Self::Handle, child: NodeOrText<Self::Handle>) {
+        let parent = self
+            .links(*sibling)
+            .parent
+            .expect("Cannot append before a sibling that has no parent");
+        let (node, position) = self.insert(parent, Some(*sibling), child);
+        self.send(ParseOperation::AppendBeforeSibling {
+            sibling: *sibling,
+            node,
+            position,
+        });
+    }
 
     fn append_based_on_parent_node(
         &mut self,
         element: &Self::Handle,
-        _prev_element: &Self::Handle,
+        prev_element: &Self::Handle,
         child: NodeOrText<Self::Handle>,
     ) {
+        let (node, position) = match self.links(*element).parent {
+            Some(parent) => self.insert(parent, Some(*element), child),
+            None => self.insert(*prev_element, None, child),
+        };
+        self.send(ParseOperation::AppendBasedOnParentNode {
+            element: *element,
+            prev_element: *prev_element,
+            node,
+            position,
+        });
     }
 
     fn append_doctype_to_document(
@@ -305,26 +956,84 @@ impl<'parse_context, 'parser: 'parse_context> TreeSink for MjDomParser<'parser>
         public_id: StrTendril,
         system_id: StrTendril,
     ) {
-        println!("Append doctype: {} {} {}", name, public_id, system_id);
+        let node_id = self.add_entry(None, false);
+        let position = self.link_node(node_id, self.document_node, None);
+        self.send(ParseOperation::AppendDoctypeToDocument {
+            node: node_id,
+            name: EcoString::from(&*name),
+            public_id: EcoString::from(&*public_id),
+            system_id: EcoString::from(&*system_id),
+            position,
+        });
     }
 
-    fn add_attrs_if_missing(&mut self, target: &Self::Handle, attrs: Vec<Attribute>) {}
+    fn add_attrs_if_missing(&mut self, target: &Self::Handle, attrs: Vec<Attribute>) {
+        let attrs = attrs
+            .into_iter()
+            .map(|attr| ParserAttribute {
+                name: attr.name,
+                value: EcoString::from(&*attr.value),
+            })
+            .collect();
+        self.send(ParseOperation::AddAttrsIfMissing {
+            target: *target,
+            attrs,
+        });
+    }
 
     fn associate_with_form(
         &mut self,
-        _target: &Self::Handle,
-        _form: &Self::Handle,
-        _nodes: (&Self::Handle, Option<&Self::Handle>),
+        target: &Self::Handle,
+        form: &Self::Handle,
+        (element, prev_element): (&Self::Handle, Option<&Self::Handle>),
     ) {
+        // The target is about to go into `element`, unless it is being foster parented, in which
+        // case it goes next to `element` if that has a parent and into `prev_element` otherwise
+        let intended_parent = match prev_element {
+            Some(prev_element) => match self.links(*element).parent {
+                Some(_) => *element,
+                None => *prev_element,
+            },
+            None => *element,
+        };
+        // A form closed off in another tree, such as by a misnested table, owns nothing more
+        if self.root(intended_parent) == self.root(*form) {
+            self.send(ParseOperation::AssociateWithForm {
+                target: *target,
+                form: *form,
+            });
+        }
     }
 
-    fn remove_from_parent(&mut self, target: &Self::Handle) {}
+    fn remove_from_parent(&mut self, target: &Self::Handle) {
+        if let Some(position) = self.unlink_node(*target) {
+            self.send(ParseOperation::RemoveFromParent {
+                target: *target,
+                position,
+            });
+        }
+    }
 
-    fn reparent_children(&mut self, node: &Self::Handle, new_parent: &Self::Handle) {}
+    fn reparent_children(&mut self, node: &Self::Handle, new_parent: &Self::Handle) {
+        let mut children = vec![];
+        while let Some(child) = self.links(*node).first_child {
+            self.unlink_node(child);
+            self.link_node(child, *new_parent, None);
+            children.push(child);
+        }
+        self.send(ParseOperation::ReparentChildren {
+            parent: *node,
+            new_parent: *new_parent,
+            children,
+        });
+    }
 
     fn mark_script_already_started(&mut self, node: &Self::Handle) {
-        todo!()
+        self.send(ParseOperation::MarkScriptAlreadyStarted { node: *node });
     }
 
-    fn set_current_line(&mut self, line_number: u64) {}
+    fn set_current_line(&mut self, line_number: u64) {
+        self.current_line = line_number;
+        self.send(ParseOperation::SetCurrentLine { line: line_number });
+    }
 }
@@ -0,0 +1,75 @@
+//! Turning the URLs written in the document into absolute ones. Relative URLs are resolved
+//! against the document's base URL, which follows the first `<base href>` and otherwise is the URL
+//! the document was fetched from.
+
+use ecow::EcoString;
+use stakker::{ret, Ret, CX};
+use url::Url;
+
+use crate::{error::DomError, parser::NodeId, MjDom};
+
+/// The URL an attribute resolved to, `None` if the element doesn't have the attribute.
+pub type ResolvedAttribute = Result<Option<Url>, DomError>;
+
+impl MjDom {
+    /// Resolve `value` against `base_url`, failing with [`DomError::Syntax`] if it isn't a URL.
+    pub(crate) fn resolve_against(base_url: &Url, value: &str) -> Result<Url, DomError> {
+        base_url
+            .join(value)
+            .map_err(|_| DomError::Syntax(value.to_string()))
+    }
+
+    /// Resolve `value` against the document's base URL.
+    pub(crate) fn resolve_url_value(&self, value: &str) -> Result<Url, DomError> {
+        Self::resolve_against(&self.document_base_url(), value)
+    }
+
+    /// Resolve `value` as written somewhere in the document, such as in a stylesheet.
+    pub fn resolve_url(
+        &mut self,
+        _cx: CX![],
+        value: EcoString,
+        callback: Ret<Result<Url, DomError>>,
+    ) {
+        ret!([callback], self.resolve_url_value(&value));
+    }
+
+    /// Resolve the attribute `qualified_name` of `node`, such as an `href`, `src` or `action`.
+    pub fn resolve_attribute(
+        &mut self,
+        _cx: CX![],
+        node: NodeId,
+        qualified_name: EcoString,
+        callback: Ret<ResolvedAttribute>,
+    ) {
+        let Some(kind) = self.tree.kind(node) else {
+            return;
+        };
+        let resolved = kind
+            .attribute(&qualified_name)
+            .map(|value| self.resolve_url_value(&value))
+            .transpose();
+        ret!([callback], resolved);
+    }
+
+    /// Resolve the attribute `qualified_name` of each of `nodes` against the same base URL, for
+    /// when a whole set of links or images is wanted at once. Released nodes are left out.
+    pub fn resolve_attributes(
+        &mut self,
+        _cx: CX![],
+        nodes: Vec<NodeId>,
+        qualified_name: EcoString,
+        callback: Ret<Vec<(NodeId, ResolvedAttribute)>>,
+    ) {
+        let base_url = self.document_base_url();
+        let resolved = nodes
+            .into_iter()
+            .filter_map(|node| {
+                let value = self.tree.kind(node)?.attribute(&qualified_name);
+                let url = value.map(|value| Self::resolve_against(&base_url, &value));
+                Some((node, url.transpose()))
+            })
+            .collect();
+        ret!([callback], resolved);
+    }
+}
@@ -0,0 +1,495 @@
+//! CSS selector matching, built on the `selectors` crate so that the same matcher can later drive
+//! the cascade. Matching walks the [`DomTree`] directly, in whichever direction the combinators
+//! ask for.
+
+use std::fmt;
+
+use cssparser::{match_ignore_ascii_case, CowRcStr, ParseError, SourceLocation, ToCss};
+use html5ever::{namespace_url, ns, LocalName, Namespace, QualName};
+use selectors::{
+    attr::{AttrSelectorOperation, CaseSensitivity, NamespaceConstraint},
+    matching::{self, ElementSelectorFlags, MatchingContext},
+    parser::{self, ParseRelative, SelectorList, SelectorParseErrorKind},
+    NthIndexCache, OpaqueElement,
+};
+
+use crate::{
+    error::DomError,
+    nodes::{DomEntry, MemberKind},
+    parser::{NodeId, DOCUMENT_NODE},
+    tree::DomTree,
+    QuirksMode,
+};
+
+/// A parsed, comma separated list of selectors.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    selectors: SelectorList<DomSelectors>,
+}
+
+impl Selector {
+    pub fn parse(selectors: &str) -> Result<Self, DomError> {
+        let mut input = cssparser::ParserInput::new(selectors);
+        let mut parser = cssparser::Parser::new(&mut input);
+        SelectorList::parse(&SelectorParser, &mut parser, ParseRelative::No)
+            .map(|selectors| Self { selectors })
+            .map_err(|_| DomError::Syntax(selectors.to_string()))
+    }
+
+    /// Every element under `scope`, or in the document when there is none, that matches this
+    /// selector, in tree order. `scope` is also what `:scope` refers to.
+    pub fn select<'a>(
+        &'a self,
+        tree: &'a DomTree,
+        scope: Option<NodeId>,
+        quirks_mode: QuirksMode,
+    ) -> impl Iterator<Item = NodeId> + 'a {
+        let root = scope.unwrap_or(DOCUMENT_NODE);
+        let scope = scope.and_then(|scope| ElementRef::new(tree, scope));
+        let mut cache = NthIndexCache::default();
+        tree.descendants(root)
+            .skip(1)
+            .filter_map(move |node| ElementRef::new(tree, node))
+            .filter(move |element| {
+                self.matches_element(element, scope.as_ref(), quirks_mode, &mut cache)
+            })
+            .map(|element| element.id)
+    }
+
+    /// Whether `node` is an element matching this selector.
+    pub fn matches(&self, tree: &DomTree, node: NodeId, quirks_mode: QuirksMode) -> bool {
+        ElementRef::new(tree, node).is_some_and(|element| {
+            self.matches_element(&element, None, quirks_mode, &mut NthIndexCache::default())
+        })
+    }
+
+    fn matches_element(
+        &self,
+        element: &ElementRef,
+        scope: Option<&ElementRef>,
+        quirks_mode: QuirksMode,
+        cache: &mut NthIndexCache,
+    ) -> bool {
+        let quirks_mode = match quirks_mode {
+            QuirksMode::Quirks => matching::QuirksMode::Quirks,
+            QuirksMode::LimitedQuirks => matching::QuirksMode::LimitedQuirks,
+            QuirksMode::NoQuirks => matching::QuirksMode::NoQuirks,
+        };
+        let mut context = MatchingContext::new(
+            matching::MatchingMode::Normal,
+            None,
+            cache,
+            quirks_mode,
+            matching::NeedsSelectorFlags::No,
+            matching::IgnoreNthChildForInvalidation::No,
+        );
+        context.scope_element = scope.map(selectors::Element::opaque);
+        self.selectors
+            .0
+            .iter()
+            .any(|selector| matching::matches_selector(selector, 0, None, element, &mut context))
+    }
+}
+
+impl ToCss for Selector {
+    fn to_css<W>(&self, dest: &mut W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        self.selectors.to_css(dest)
+    }
+}
+
+/// An element in a [`DomTree`], as seen by the matcher.
+#[derive(Clone, Copy)]
+struct ElementRef<'a> {
+    tree: &'a DomTree,
+    id: NodeId,
+}
+
+impl<'a> ElementRef<'a> {
+    fn new(tree: &'a DomTree, id: NodeId) -> Option<Self> {
+        matches!(tree.kind(id)?, MemberKind::Element { .. }).then_some(Self { tree, id })
+    }
+
+    fn entry(&self) -> &'a DomEntry {
+        self.tree
+            .get(self.id)
+            .expect("Could not find element in DOM")
+    }
+
+    fn name(&self) -> &'a QualName {
+        match &self.entry().myself {
+            MemberKind::Element { name, .. } => name,
+            _ => unreachable!("Only elements are matched against"),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        let MemberKind::Element { attrs, .. } = &self.entry().myself else {
+            return None;
+        };
+        attrs
+            .iter()
+            .find(|(key, _)| key.ns == ns!() && &*key.local == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn is_html(&self, local_names: &[&str]) -> bool {
+        let name = self.name();
+        name.ns == ns!(html) && local_names.contains(&&*name.local)
+    }
+
+    /// Walk from `start` along `step` until reaching an element.
+    fn find_element(
+        &self,
+        start: Option<NodeId>,
+        step: impl Fn(&DomEntry) -> Option<NodeId>,
+    ) -> Option<Self> {
+        let mut node = start;
+        while let Some(current) = node {
+            if let Some(element) = Self::new(self.tree, current) {
+                return Some(element);
+            }
+            node = step(self.tree.get(current)?);
+        }
+        None
+    }
+
+    fn is_checked(&self) -> bool {
+        if self.is_html(&["input"]) {
+            let checkable = self.attribute("type").is_some_and(|kind| {
+                kind.eq_ignore_ascii_case("checkbox") || kind.eq_ignore_ascii_case("radio")
+            });
+            checkable && self.attribute("checked").is_some()
+        } else {
+            self.is_html(&["option"]) && self.attribute("selected").is_some()
+        }
+    }
+
+    /// Form controls that can be disabled. Disabling a `fieldset` doesn't yet carry over to the
+    /// controls inside it.
+    fn is_form_control(&self) -> bool {
+        self.is_html(&[
+            "button", "input", "select", "textarea", "optgroup", "option", "fieldset",
+        ])
+    }
+}
+
+impl fmt::Debug for ElementRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ElementRef")
+            .field("id", &self.id)
+            .field("name", &self.name().local)
+            .finish()
+    }
+}
+
+impl selectors::Element for ElementRef<'_> {
+    type Impl = DomSelectors;
+
+    fn opaque(&self) -> OpaqueElement {
+        OpaqueElement::new(self.entry())
+    }
+
+    fn parent_element(&self) -> Option<Self> {
+        Self::new(self.tree, self.entry().parent?)
+    }
+
+    fn parent_node_is_shadow_root(&self) -> bool {
+        false
+    }
+
+    fn containing_shadow_host(&self) -> Option<Self> {
+        None
+    }
+
+    fn is_pseudo_element(&self) -> bool {
+        false
+    }
+
+    fn prev_sibling_element(&self) -> Option<Self> {
+        self.find_element(self.entry().previous_sibling, |entry| {
+            entry.previous_sibling
+        })
+    }
+
+    fn next_sibling_element(&self) -> Option<Self> {
+        self.find_element(self.entry().next_sibling, |entry| entry.next_sibling)
+    }
+
+    fn first_element_child(&self) -> Option<Self> {
+        self.find_element(self.entry().first_child, |entry| entry.next_sibling)
+    }
+
+    fn is_html_element_in_html_document(&self) -> bool {
+        self.name().ns == ns!(html)
+    }
+
+    fn has_local_name(&self, local_name: &CssLocalName) -> bool {
+        self.name().local == local_name.0
+    }
+
+    fn has_namespace(&self, namespace: &Namespace) -> bool {
+        self.name().ns == *namespace
+    }
+
+    fn is_same_type(&self, other: &Self) -> bool {
+        self.name().local == other.name().local && self.name().ns == other.name().ns
+    }
+
+    fn attr_matches(
+        &self,
+        namespace: &NamespaceConstraint<&Namespace>,
+        local_name: &CssLocalName,
+        operation: &AttrSelectorOperation<&CssString>,
+    ) -> bool {
+        let MemberKind::Element { attrs, .. } = &self.entry().myself else {
+            return false;
+        };
+        attrs.iter().any(|(key, value)| {
+            !matches!(namespace, NamespaceConstraint::Specific(url) if **url != key.ns)
+                && key.local == local_name.0
+                && operation.eval_str(value)
+        })
+    }
+
+    fn match_non_ts_pseudo_class(
+        &self,
+        pseudo_class: &NonTSPseudoClass,
+        _context: &mut MatchingContext<DomSelectors>,
+    ) -> bool {
+        match pseudo_class {
+            NonTSPseudoClass::Link | NonTSPseudoClass::AnyLink => self.is_link(),
+            // Nothing is ever visited, and there is no user to hover or focus anything yet
+            NonTSPseudoClass::Visited
+            | NonTSPseudoClass::Hover
+            | NonTSPseudoClass::Active
+            | NonTSPseudoClass::Focus => false,
+            NonTSPseudoClass::Checked => self.is_checked(),
+            NonTSPseudoClass::Disabled => {
+                self.is_form_control() && self.attribute("disabled").is_some()
+            }
+            NonTSPseudoClass::Enabled => {
+                self.is_form_control() && self.attribute("disabled").is_none()
+            }
+        }
+    }
+
+    fn match_pseudo_element(
+        &self,
+        _pseudo_element: &PseudoElement,
+        _context: &mut MatchingContext<DomSelectors>,
+    ) -> bool {
+        false
+    }
+
+    fn apply_selector_flags(&self, _flags: ElementSelectorFlags) {}
+
+    fn is_link(&self) -> bool {
+        self.is_html(&["a", "area", "link"]) && self.attribute("href").is_some()
+    }
+
+    fn is_html_slot_element(&self) -> bool {
+        self.is_html(&["slot"])
+    }
+
+    fn has_id(&self, id: &CssLocalName, case_sensitivity: CaseSensitivity) -> bool {
+        self.attribute("id")
+            .is_some_and(|value| case_sensitivity.eq(id.0.as_bytes(), value.as_bytes()))
+    }
+
+    fn has_class(&self, name: &CssLocalName, case_sensitivity: CaseSensitivity) -> bool {
+        self.attribute("class").is_some_and(|classes| {
+            classes
+                .split_ascii_whitespace()
+                .any(|class| case_sensitivity.eq(name.0.as_bytes(), class.as_bytes()))
+        })
+    }
+
+    fn imported_part(&self, _name: &CssLocalName) -> Option<CssLocalName> {
+        None
+    }
+
+    fn is_part(&self, _name: &CssLocalName) -> bool {
+        false
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tree
+            .children(self.id)
+            .filter_map(|child| self.tree.kind(child))
+            .all(|kind| match kind {
+                MemberKind::Element { .. } => false,
+                MemberKind::Text { contents } => contents.is_empty(),
+                _ => true,
+            })
+    }
+
+    fn is_root(&self) -> bool {
+        let parent = self.entry().parent;
+        matches!(
+            parent.and_then(|parent| self.tree.kind(parent)),
+            Some(MemberKind::Document)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DomSelectors;
+
+impl parser::SelectorImpl for DomSelectors {
+    type ExtraMatchingData<'a> = ();
+    type AttrValue = CssString;
+    type Identifier = CssLocalName;
+    type LocalName = CssLocalName;
+    type NamespaceUrl = Namespace;
+    type NamespacePrefix = CssLocalName;
+    type BorrowedNamespaceUrl = Namespace;
+    type BorrowedLocalName = CssLocalName;
+    type NonTSPseudoClass = NonTSPseudoClass;
+    type PseudoElement = PseudoElement;
+}
+
+struct SelectorParser;
+
+impl<'i> parser::Parser<'i> for SelectorParser {
+    type Impl = DomSelectors;
+    type Error = SelectorParseErrorKind<'i>;
+
+    fn parse_nth_child_of(&self) -> bool {
+        true
+    }
+
+    fn parse_is_and_where(&self) -> bool {
+        true
+    }
+
+    fn parse_non_ts_pseudo_class(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>,
+    ) -> Result<NonTSPseudoClass, ParseError<'i, Self::Error>> {
+        let pseudo_class = match_ignore_ascii_case! { &name,
+            "link" => NonTSPseudoClass::Link,
+            "any-link" => NonTSPseudoClass::AnyLink,
+            "visited" => NonTSPseudoClass::Visited,
+            "hover" => NonTSPseudoClass::Hover,
+            "active" => NonTSPseudoClass::Active,
+            "focus" => NonTSPseudoClass::Focus,
+            "checked" => NonTSPseudoClass::Checked,
+            "disabled" => NonTSPseudoClass::Disabled,
+            "enabled" => NonTSPseudoClass::Enabled,
+            _ => return Err(location.new_custom_error(
+                SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+            )),
+        };
+        Ok(pseudo_class)
+    }
+}
+
+/// An attribute value in a selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CssString(pub String);
+
+impl<'a> From<&'a str> for CssString {
+    fn from(value: &'a str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl AsRef<str> for CssString {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ToCss for CssString {
+    fn to_css<W>(&self, dest: &mut W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        cssparser::serialize_string(&self.0, dest)
+    }
+}
+
+/// A tag, attribute, id or class name in a selector.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CssLocalName(pub LocalName);
+
+impl<'a> From<&'a str> for CssLocalName {
+    fn from(value: &'a str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl ToCss for CssLocalName {
+    fn to_css<W>(&self, dest: &mut W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        dest.write_str(&self.0)
+    }
+}
+
+/// The pseudo-classes that depend on an element's state rather than its place in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonTSPseudoClass {
+    Link,
+    AnyLink,
+    Visited,
+    Hover,
+    Active,
+    Focus,
+    Checked,
+    Disabled,
+    Enabled,
+}
+
+impl parser::NonTSPseudoClass for NonTSPseudoClass {
+    type Impl = DomSelectors;
+
+    fn is_active_or_hover(&self) -> bool {
+        matches!(self, Self::Active | Self::Hover)
+    }
+
+    fn is_user_action_state(&self) -> bool {
+        matches!(self, Self::Active | Self::Hover | Self::Focus)
+    }
+}
+
+impl ToCss for NonTSPseudoClass {
+    fn to_css<W>(&self, dest: &mut W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        dest.write_str(match self {
+            Self::Link => ":link",
+            Self::AnyLink => ":any-link",
+            Self::Visited => ":visited",
+            Self::Hover => ":hover",
+            Self::Active => ":active",
+            Self::Focus => ":focus",
+            Self::Checked => ":checked",
+            Self::Disabled => ":disabled",
+            Self::Enabled => ":enabled",
+        })
+    }
+}
+
+/// Pseudo-elements never match DOM queries, so none are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoElement {}
+
+impl parser::PseudoElement for PseudoElement {
+    type Impl = DomSelectors;
+}
+
+impl ToCss for PseudoElement {
+    fn to_css<W>(&self, _dest: &mut W) -> fmt::Result
+    where
+        W: fmt::Write,
+    {
+        match *self {}
+    }
+}
@@ -0,0 +1,148 @@
+//! The HTML fragment serialization algorithm, run over the [`DomTree`].
+
+use html5ever::{namespace_url, ns, LocalName, QualName};
+
+use crate::{
+    nodes::{attributes::qualified_name, DomEntry, MemberKind},
+    tree::DomTree,
+};
+
+/// Elements that never have contents or an end tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements whose text is written out as is. `noscript` is among them because the parser runs
+/// with scripting enabled and so never parses its contents as markup.
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "style",
+    "script",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+    "noscript",
+];
+
+/// The markup for `node` and everything under it.
+pub fn outer_html(tree: &DomTree, node: &DomEntry) -> String {
+    let mut output = String::new();
+    serialize_node(tree, node, None, &mut output);
+    output
+}
+
+/// The markup for the children of `node`, or of its contents if it is a template. Serializing a
+/// document gives the whole page, doctype included.
+pub fn inner_html(tree: &DomTree, node: &DomEntry) -> String {
+    let mut output = String::new();
+    serialize_children(tree, node, &mut output);
+    output
+}
+
+fn serialize_children(tree: &DomTree, node: &DomEntry, output: &mut String) {
+    let parent = match &node.myself {
+        MemberKind::Element { name, .. } => Some(name),
+        _ => None,
+    };
+    let node = node.template_contents.unwrap_or(node.id);
+    for child in tree.children(node).filter_map(|child| tree.get(child)) {
+        serialize_node(tree, child, parent, output);
+    }
+}
+
+fn is_html(name: &QualName, local_names: &[&str]) -> bool {
+    name.ns == ns!(html) && local_names.contains(&&*name.local)
+}
+
+fn serialize_node(tree: &DomTree, node: &DomEntry, parent: Option<&QualName>, output: &mut String) {
+    match &node.myself {
+        MemberKind::Document | MemberKind::DocumentFragment => {
+            serialize_children(tree, node, output)
+        }
+        MemberKind::Element { name, attrs } => {
+            let tag_name = tag_name(name);
+            output.push('<');
+            output.push_str(&tag_name);
+            for (name, value) in attrs {
+                output.push(' ');
+                output.push_str(&attribute_name(name));
+                output.push_str("=\"");
+                escape(value, true, output);
+                output.push('"');
+            }
+            output.push('>');
+            if is_html(name, VOID_ELEMENTS) {
+                return;
+            }
+            serialize_children(tree, node, output);
+            output.push_str("</");
+            output.push_str(&tag_name);
+            output.push('>');
+        }
+        MemberKind::Text { contents } => match parent {
+            Some(parent) if is_html(parent, RAW_TEXT_ELEMENTS) => output.push_str(contents),
+            _ => escape(contents, false, output),
+        },
+        MemberKind::Comment { content } => {
+            output.push_str("<!--");
+            output.push_str(content);
+            output.push_str("-->");
+        }
+        MemberKind::ProcessingInstruction { target, data } => {
+            output.push_str("<?");
+            output.push_str(target);
+            output.push(' ');
+            output.push_str(data);
+            output.push('>');
+        }
+        MemberKind::Doctype { name, .. } => {
+            output.push_str("<!DOCTYPE ");
+            output.push_str(name);
+            output.push('>');
+        }
+    }
+}
+
+fn tag_name(name: &QualName) -> String {
+    if name.ns == ns!(html) || name.ns == ns!(svg) || name.ns == ns!(mathml) {
+        name.local.to_string()
+    } else {
+        qualified_name(name)
+    }
+}
+
+fn attribute_name(name: &QualName) -> String {
+    let prefixed = |prefix: &str, local: &LocalName| format!("{}:{}", prefix, local);
+    if name.ns == ns!() {
+        name.local.to_string()
+    } else if name.ns == ns!(xml) {
+        prefixed("xml", &name.local)
+    } else if name.ns == ns!(xmlns) {
+        if &*name.local == "xmlns" {
+            name.local.to_string()
+        } else {
+            prefixed("xmlns", &name.local)
+        }
+    } else if name.ns == ns!(xlink) {
+        prefixed("xlink", &name.local)
+    } else {
+        qualified_name(name)
+    }
+}
+
+/// Escape text, or a double-quoted attribute value when `attribute_mode` is set. The spec escapes
+/// `<` and `>` in attribute values too, so that serialized markup can't be reparsed into a tag.
+fn escape(text: &str, attribute_mode: bool, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '\u{A0}' => output.push_str("&nbsp;"),
+            '"' if attribute_mode => output.push_str("&quot;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            c => output.push(c),
+        }
+    }
+}
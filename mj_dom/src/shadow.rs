@@ -0,0 +1,338 @@
+//! Shadow trees: a fragment attached to a host element, whose children are what gets rendered in
+//! place of the host's own, and the slots in it that the host's children are shown through.
+//!
+//! A shadow root is kept like a template's contents. It is not a child of its host, so walking
+//! the tree through the child links never enters it, and [`DomTree::root`](crate::tree::DomTree)
+//! of anything in it is the shadow root itself. The flat tree, with shadow trees in place of their
+//! hosts' children and slots holding what is assigned to them, is what rendering walks instead.
+
+use std::collections::VecDeque;
+
+use ecow::EcoString;
+use html5ever::{namespace_url, ns, QualName};
+use stakker::{actor, ret, ret_nop, ActorOwn, Ret, CX};
+
+use crate::{
+    dom_iterator::ForwardDomIterator,
+    error::DomError,
+    nodes::MemberKind,
+    parser::{NodeId, ParserInput},
+    tree::DomTree,
+    MjDom, DOCUMENT_NODE,
+};
+
+/// The HTML elements a shadow root can be attached to, besides custom elements.
+const SHADOW_HOSTS: [&str; 18] = [
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "div",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "main",
+    "nav",
+    "p",
+    "section",
+    "span",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowRootMode {
+    /// The shadow root can be reached from its host.
+    Open,
+    /// Only whoever attached the shadow root knows about it.
+    Closed,
+}
+
+impl ShadowRootMode {
+    /// The mode a `shadowrootmode` attribute asks for, if it is a valid one.
+    pub fn from_attribute(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("open") {
+            Some(Self::Open)
+        } else if value.eq_ignore_ascii_case("closed") {
+            Some(Self::Closed)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a shadow root is to be attached, as for `attachShadow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadowRootInit {
+    pub mode: ShadowRootMode,
+    pub delegates_focus: bool,
+    pub clonable: bool,
+    pub serializable: bool,
+}
+
+impl ShadowRootInit {
+    pub fn new(mode: ShadowRootMode) -> Self {
+        Self {
+            mode,
+            delegates_focus: false,
+            clonable: false,
+            serializable: false,
+        }
+    }
+}
+
+/// What makes a fragment a shadow root: the host it is attached to, and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadowRoot {
+    pub host: NodeId,
+    pub init: ShadowRootInit,
+    /// Whether the parser attached it from a `<template shadowrootmode>`.
+    pub declarative: bool,
+}
+
+/// Whether an element named `name` can have a shadow root attached: one of a handful of HTML
+/// elements, or anything with a valid custom element name.
+pub fn is_valid_shadow_host(name: &QualName) -> bool {
+    if name.ns != ns!(html) {
+        return false;
+    }
+    let local = &*name.local;
+    SHADOW_HOSTS.contains(&local) || is_custom_element_name(local)
+}
+
+/// A rough check for a valid custom element name: a lowercase ASCII letter followed by anything
+/// with a hyphen in it. The few names reserved by SVG and MathML are ruled out.
+fn is_custom_element_name(name: &str) -> bool {
+    const RESERVED: [&str; 8] = [
+        "annotation-xml",
+        "color-profile",
+        "font-face",
+        "font-face-src",
+        "font-face-uri",
+        "font-face-format",
+        "font-face-name",
+        "missing-glyph",
+    ];
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.contains('-')
+        && !name.contains(|c: char| c.is_ascii_uppercase())
+        && !RESERVED.contains(&name)
+}
+
+/// The name a slot takes assignments by, or a slottable asks to be assigned by.
+fn slot_name(kind: &MemberKind, attribute: &str) -> EcoString {
+    kind.attribute(attribute).unwrap_or_default()
+}
+
+fn is_slot(kind: &MemberKind) -> bool {
+    kind.is_element(&ns!(html), "slot")
+}
+
+impl DomTree {
+    /// The shadow root `node` is, if it is one.
+    pub fn shadow(&self, node: NodeId) -> Option<&ShadowRoot> {
+        self.get(node)?.shadow.as_ref()
+    }
+
+    /// The top of the tree `node` is in, carrying on from each shadow root to its host. For
+    /// anything connected this is the document.
+    pub fn shadow_including_root(&self, node: NodeId) -> NodeId {
+        let mut root = self.root(node);
+        while let Some(shadow) = self.shadow(root) {
+            root = self.root(shadow.host);
+        }
+        root
+    }
+
+    /// The slot `node` is assigned to, if its parent is a shadow host with a slot by the name
+    /// `node` asks for. Only elements and text can be slotted.
+    pub fn assigned_slot(&self, node: NodeId) -> Option<NodeId> {
+        let kind = self.kind(node)?;
+        let name = match kind {
+            MemberKind::Element { .. } => slot_name(kind, "slot"),
+            MemberKind::Text { .. } => EcoString::new(),
+            _ => return None,
+        };
+        let shadow_root = self.get(self.parent(node)?)?.shadow_root?;
+        // The first slot in tree order by that name takes everything asking for it
+        self.descendants(shadow_root).find(|&slot| {
+            self.kind(slot)
+                .is_some_and(|kind| is_slot(kind) && slot_name(kind, "name") == name)
+        })
+    }
+
+    /// The nodes assigned to the slot `slot`, in tree order.
+    pub fn assigned_nodes(&self, slot: NodeId) -> Vec<NodeId> {
+        let Some(shadow) = self.shadow(self.root(slot)) else {
+            return vec![];
+        };
+        self.children(shadow.host)
+            .filter(|&child| self.assigned_slot(child) == Some(slot))
+            .collect()
+    }
+
+    /// The children of `node` in the flat tree: the children of its shadow root for a shadow
+    /// host, what is assigned to a slot or else the slot's own children as a fallback, and
+    /// otherwise just its children.
+    pub fn flat_children(&self, node: NodeId) -> Vec<NodeId> {
+        if let Some(shadow_root) = self.get(node).and_then(|entry| entry.shadow_root) {
+            return self.children(shadow_root).collect();
+        }
+        let in_shadow_tree = self.shadow(self.root(node)).is_some();
+        if in_shadow_tree && self.kind(node).is_some_and(is_slot) {
+            let assigned = self.assigned_nodes(node);
+            if !assigned.is_empty() {
+                return assigned;
+            }
+        }
+        self.children(node).collect()
+    }
+
+    /// `node` followed by everything under it in the flat tree, in order.
+    pub fn flat_descendants(&self, node: NodeId) -> Vec<NodeId> {
+        let mut found = vec![];
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if !self.contains(node) {
+                continue;
+            }
+            found.push(node);
+            pending.extend(self.flat_children(node).into_iter().rev());
+        }
+        found
+    }
+}
+
+impl MjDom {
+    /// Attach a shadow root to `host`, calling back with it once the parser has allocated it.
+    /// Fails with [`DomError::NotSupported`] if `host` can't have one or already has one, unless
+    /// the one it has was declared in the markup with the same mode, in which case that one is
+    /// emptied and handed back instead.
+    pub fn attach_shadow(
+        &mut self,
+        cx: CX![],
+        host: NodeId,
+        init: ShadowRootInit,
+        callback: Ret<Result<NodeId, DomError>>,
+    ) {
+        let Some(entry) = self.tree.get(host) else {
+            return;
+        };
+        if !entry.element_name().is_some_and(is_valid_shadow_host) {
+            ret!([callback], Err(DomError::NotSupported));
+            return;
+        }
+        if let Some(shadow_root) = entry.shadow_root {
+            let reusable = self
+                .tree
+                .shadow(shadow_root)
+                .is_some_and(|shadow| shadow.declarative && shadow.init.mode == init.mode);
+            if !reusable {
+                ret!([callback], Err(DomError::NotSupported));
+                return;
+            }
+            for child in self.tree.children(shadow_root).collect::<Vec<_>>() {
+                // The children were checked to be there a moment ago
+                let _ = self.remove_node(cx, Some(shadow_root), child);
+            }
+            if let Some(shadow) = self
+                .tree
+                .get_mut(shadow_root)
+                .and_then(|entry| entry.shadow.as_mut())
+            {
+                shadow.declarative = false;
+            }
+            ret!([callback], Ok(shadow_root));
+            return;
+        }
+        self.shadow_callbacks.push_back(callback);
+        self.parser.send(ParserInput::AttachShadow { host, init });
+    }
+
+    /// Make `root` the shadow root of `host`. A declarative one comes from the contents of
+    /// `template`, which is let go now that it has done its job.
+    pub(crate) fn link_shadow_root(
+        &mut self,
+        cx: CX![],
+        host: NodeId,
+        root: NodeId,
+        template: Option<NodeId>,
+        init: ShadowRootInit,
+    ) -> Result<(), DomError> {
+        let Some(entry) = self.tree.get(host) else {
+            return Err(DomError::NotFound);
+        };
+        if entry.shadow_root.is_some() {
+            return Err(DomError::NotSupported);
+        }
+        match template {
+            Some(template) => {
+                // The template only stood in for the shadow root while it was being parsed
+                if let Some(template) = self.tree.get_mut(template) {
+                    template.template_contents = None;
+                }
+                self.schedule_release(cx, template);
+            }
+            None => self.create_entry(cx, root, MemberKind::DocumentFragment, self.current_line),
+        }
+        if let Some(root) = self.tree.get_mut(root) {
+            root.template_owner = None;
+            root.shadow = Some(ShadowRoot {
+                host,
+                init,
+                declarative: template.is_some(),
+            });
+        }
+        if let Some(host) = self.tree.get_mut(host) {
+            host.shadow_root = Some(root);
+        }
+        Ok(())
+    }
+
+    /// The shadow root of `host`, unless there is none or it is closed, as for `shadowRoot`.
+    pub fn shadow_root(&mut self, _cx: CX![], host: NodeId, callback: Ret<Option<NodeId>>) {
+        self.answer(host, callback, |entry| {
+            entry.shadow_root.filter(|&root| {
+                self.tree
+                    .shadow(root)
+                    .is_some_and(|shadow| shadow.init.mode == ShadowRootMode::Open)
+            })
+        });
+    }
+
+    /// What makes `node` a shadow root, if it is one.
+    pub fn shadow(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<ShadowRoot>>) {
+        self.answer(node, callback, |entry| entry.shadow);
+    }
+
+    pub fn assigned_slot(&mut self, _cx: CX![], node: NodeId, callback: Ret<Option<NodeId>>) {
+        if self.tree.contains(node) {
+            ret!([callback], self.tree.assigned_slot(node));
+        }
+    }
+
+    /// The nodes assigned to the slot `slot`, as for `assignedNodes()`.
+    pub fn assigned_nodes(&mut self, _cx: CX![], slot: NodeId, callback: Ret<Vec<NodeId>>) {
+        if self.tree.contains(slot) {
+            ret!([callback], self.tree.assigned_nodes(slot));
+        }
+    }
+
+    pub fn flat_children(&mut self, _cx: CX![], node: NodeId, callback: Ret<Vec<NodeId>>) {
+        if self.tree.contains(node) {
+            ret!([callback], self.tree.flat_children(node));
+        }
+    }
+
+    /// Like [`MjDom::iter`], but walking the flat tree, as rendering sees the document.
+    pub fn flat_iter(&mut self, cx: CX![], callback: Ret<ActorOwn<ForwardDomIterator>>) {
+        let nodes = VecDeque::from(self.tree.flat_descendants(DOCUMENT_NODE));
+        ret!(
+            [callback],
+            actor!(cx, ForwardDomIterator::init(nodes), ret_nop!()) as ActorOwn<ForwardDomIterator>
+        );
+    }
+}
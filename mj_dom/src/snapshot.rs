@@ -0,0 +1,99 @@
+//! Immutable copies of the tree that can be handed to other threads. Layout and style work can run
+//! against a [`DomSnapshot`] while [`MjDom`](crate::MjDom) carries on changing the live tree, and
+//! the version tells them whether what they computed is still current.
+
+use std::{iter, sync::Arc};
+
+use ecow::EcoString;
+
+use crate::{nodes::MemberKind, parser::NodeId, shadow::ShadowRoot};
+
+/// One node as it was when the snapshot was taken.
+#[derive(Debug)]
+pub struct SnapshotNode {
+    pub id: NodeId,
+    pub parent: Option<NodeId>,
+    pub first_child: Option<NodeId>,
+    pub last_child: Option<NodeId>,
+    pub previous_sibling: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+    pub template_contents: Option<NodeId>,
+    pub shadow_root: Option<NodeId>,
+    /// What makes this node a shadow root, if it is one.
+    pub shadow: Option<ShadowRoot>,
+    pub kind: MemberKind,
+}
+
+/// The whole tree at one [`version`](DomSnapshot::version), indexed by [`NodeId`] like the
+/// [`DomTree`](crate::tree::DomTree) it was taken from. Nodes that haven't changed since the
+/// previous snapshot are shared with it rather than copied again.
+#[derive(Clone, Debug)]
+pub struct DomSnapshot {
+    pub(crate) version: u64,
+    pub(crate) nodes: Vec<Option<Arc<SnapshotNode>>>,
+}
+
+impl DomSnapshot {
+    /// The version of the tree this is a copy of. Versions only go up, so a later snapshot
+    /// always has a higher one, and an equal one means nothing has changed in between.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.get(node).is_some()
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&SnapshotNode> {
+        self.nodes.get(node)?.as_deref()
+    }
+
+    pub fn kind(&self, node: NodeId) -> Option<&MemberKind> {
+        self.get(node).map(|node| &node.kind)
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.get(node)?.parent
+    }
+
+    /// The children of `node` in order.
+    pub fn children(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let first_child = self.get(node).and_then(|node| node.first_child);
+        iter::successors(first_child, |&child| self.get(child)?.next_sibling)
+    }
+
+    /// `node` followed by everything under it in tree order, leaving out template contents.
+    pub fn descendants(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let following = move |&current: &NodeId| {
+            let entry = self.get(current)?;
+            if entry.first_child.is_some() {
+                return entry.first_child;
+            }
+            let mut current = current;
+            while current != node {
+                let entry = self.get(current)?;
+                if entry.next_sibling.is_some() {
+                    return entry.next_sibling;
+                }
+                current = entry.parent?;
+            }
+            None
+        };
+        iter::successors(self.contains(node).then_some(node), following)
+    }
+
+    /// The value of the attribute with the qualified name `qualified_name` on the element `node`.
+    pub fn attribute(&self, node: NodeId, qualified_name: &str) -> Option<EcoString> {
+        self.kind(node)?.attribute(qualified_name)
+    }
+
+    /// The text of `node` and everything under it, as for `textContent`.
+    pub fn text_content(&self, node: NodeId) -> String {
+        self.descendants(node)
+            .filter_map(|node| match self.kind(node)? {
+                MemberKind::Text { contents } => Some(contents.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
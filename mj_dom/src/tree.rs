@@ -0,0 +1,307 @@
+//! The arena holding every node of the document. [`MjDom`](crate::MjDom) owns it, so walking the
+//! tree is plain indexing rather than a message per step, and only requests from outside go
+//! through the actor.
+
+use std::{collections::HashSet, iter, sync::Arc};
+
+use crate::{
+    nodes::{DomEntry, DomSubtree, MemberKind},
+    parser::{NodeId, ParserPosition},
+    snapshot::{DomSnapshot, SnapshotNode},
+};
+
+/// The entries of one document, indexed by [`NodeId`]. The parser hands out ids in order, so the
+/// slots are densely used, and released entries just leave a gap until the next document.
+#[derive(Default)]
+pub struct DomTree {
+    entries: Vec<Option<DomEntry>>,
+    len: usize,
+    /// Goes up with every change, and stamps the snapshots taken of the tree.
+    version: u64,
+    /// The last snapshot taken, which the next one shares everything unchanged with.
+    snapshot: Option<Arc<DomSnapshot>>,
+    /// The entries changed since `snapshot` was taken. Nothing is tracked while there isn't one.
+    changed: HashSet<NodeId>,
+}
+
+impl DomTree {
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+        self.version += 1;
+        self.snapshot = None;
+        self.changed.clear();
+    }
+
+    /// Note that `node` is about to change.
+    fn touch(&mut self, node: NodeId) {
+        self.version += 1;
+        if self.snapshot.is_some() {
+            self.changed.insert(node);
+        }
+    }
+
+    /// The version the tree is at, as a snapshot taken now would be stamped with.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// An immutable copy of the tree as it stands. Taking one again without any change in between
+    /// returns the same snapshot, and otherwise only the entries that changed are copied.
+    pub(crate) fn snapshot(&mut self) -> Arc<DomSnapshot> {
+        if let Some(snapshot) = &self.snapshot {
+            if snapshot.version == self.version {
+                return snapshot.clone();
+            }
+        }
+        let nodes = match self.snapshot.take() {
+            Some(previous) => {
+                let mut nodes = Arc::unwrap_or_clone(previous).nodes;
+                nodes.resize(self.entries.len(), None);
+                for node in self.changed.drain() {
+                    if let Some(slot) = nodes.get_mut(node) {
+                        *slot = self.entries[node].as_ref().map(snapshot_node);
+                    }
+                }
+                nodes
+            }
+            None => self
+                .entries
+                .iter()
+                .map(|entry| entry.as_ref().map(snapshot_node))
+                .collect(),
+        };
+        let snapshot = Arc::new(DomSnapshot {
+            version: self.version,
+            nodes,
+        });
+        self.snapshot = Some(snapshot.clone());
+        snapshot
+    }
+
+    /// How many entries are live, not counting released ones.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.get(node).is_some()
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&DomEntry> {
+        self.entries.get(node)?.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, node: NodeId) -> Option<&mut DomEntry> {
+        self.touch(node);
+        self.entries.get_mut(node)?.as_mut()
+    }
+
+    fn entry_mut(&mut self, node: NodeId) -> &mut DomEntry {
+        self.get_mut(node).expect("Could not find element in DOM")
+    }
+
+    pub fn kind(&self, node: NodeId) -> Option<&MemberKind> {
+        self.get(node).map(|entry| &entry.myself)
+    }
+
+    pub(crate) fn insert(&mut self, entry: DomEntry) {
+        let node = entry.id;
+        self.touch(node);
+        if node >= self.entries.len() {
+            self.entries.resize_with(node + 1, || None);
+        }
+        if self.entries[node].replace(entry).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// Drop the entry for `node`, which should already be detached from everything else.
+    pub(crate) fn remove(&mut self, node: NodeId) -> Option<DomEntry> {
+        self.touch(node);
+        let entry = self.entries.get_mut(node)?.take()?;
+        self.len -= 1;
+        Some(entry)
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.get(node)?.parent
+    }
+
+    /// The children of `node` in order.
+    pub fn children(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let first_child = self.get(node).and_then(|entry| entry.first_child);
+        iter::successors(first_child, |&child| self.get(child)?.next_sibling)
+    }
+
+    /// The parent of `node`, its parent in turn and so on up to the root.
+    pub fn ancestors(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        iter::successors(self.parent(node), |&node| self.parent(node))
+    }
+
+    /// `node` followed by everything under it in tree order. Template contents aren't children
+    /// of their template, so they are left out.
+    pub fn descendants(&self, node: NodeId) -> Descendants<'_> {
+        Descendants {
+            tree: self,
+            root: node,
+            next: self.contains(node).then_some(node),
+        }
+    }
+
+    /// The text of `node` and everything under it, as for `textContent`.
+    pub fn text_content(&self, node: NodeId) -> String {
+        self.descendants(node)
+            .filter_map(|node| match self.kind(node)? {
+                MemberKind::Text { contents } => Some(contents.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether `node` is `ancestor` or somewhere underneath it.
+    pub fn is_inclusive_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        node == ancestor || self.ancestors(node).any(|node| node == ancestor)
+    }
+
+    /// The top of the tree `node` is in. Template contents belong to the tree their template is
+    /// in, even though they aren't its children.
+    pub fn root(&self, mut node: NodeId) -> NodeId {
+        while let Some(entry) = self.get(node) {
+            match entry.parent.or(entry.template_owner) {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+        node
+    }
+
+    /// `node` and everything underneath it, template contents and shadow trees included, in no
+    /// particular order.
+    pub fn subtree_nodes(&self, node: NodeId) -> Vec<NodeId> {
+        let mut found = vec![];
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            let Some(entry) = self.get(node) else {
+                continue;
+            };
+            found.push(node);
+            pending.extend(
+                self.children(node)
+                    .chain(entry.template_contents)
+                    .chain(entry.shadow_root),
+            );
+        }
+        found
+    }
+
+    /// An owned copy of `node` and its descendants.
+    pub fn subtree(&self, node: NodeId) -> Option<DomSubtree> {
+        let entry = self.get(node)?;
+        Some(DomSubtree {
+            id: node,
+            kind: entry.myself.clone(),
+            children: self
+                .children(node)
+                .filter_map(|child| self.subtree(child))
+                .collect(),
+            template_contents: entry
+                .template_contents
+                .and_then(|contents| self.subtree(contents))
+                .map(Box::new),
+        })
+    }
+
+    /// Put `node` among the children of `parent`, before `next` or else last, taking it away from
+    /// wherever it was before.
+    pub(crate) fn attach(&mut self, node: NodeId, parent: NodeId, next: Option<NodeId>) {
+        self.detach(node);
+        let previous = match next {
+            Some(next) => self.entry_mut(next).previous_sibling.replace(node),
+            None => self.entry_mut(parent).last_child.replace(node),
+        };
+        match previous {
+            Some(previous) => self.entry_mut(previous).next_sibling = Some(node),
+            None => self.entry_mut(parent).first_child = Some(node),
+        }
+        let entry = self.entry_mut(node);
+        entry.parent = Some(parent);
+        entry.previous_sibling = previous;
+        entry.next_sibling = next;
+    }
+
+    /// Take `node` away from its parent, closing the gap it leaves. Returns where it was, if it
+    /// had a parent at all.
+    pub(crate) fn detach(&mut self, node: NodeId) -> Option<ParserPosition> {
+        let entry = self.get_mut(node)?;
+        let position = ParserPosition {
+            parent: entry.parent.take()?,
+            previous: entry.previous_sibling.take(),
+            next: entry.next_sibling.take(),
+        };
+        match position.previous {
+            Some(previous) => self.entry_mut(previous).next_sibling = position.next,
+            None => self.entry_mut(position.parent).first_child = position.next,
+        }
+        match position.next {
+            Some(next) => self.entry_mut(next).previous_sibling = position.previous,
+            None => self.entry_mut(position.parent).last_child = position.previous,
+        }
+        Some(position)
+    }
+}
+
+fn snapshot_node(entry: &DomEntry) -> Arc<SnapshotNode> {
+    Arc::new(SnapshotNode {
+        id: entry.id,
+        parent: entry.parent,
+        first_child: entry.first_child,
+        last_child: entry.last_child,
+        previous_sibling: entry.previous_sibling,
+        next_sibling: entry.next_sibling,
+        template_contents: entry.template_contents,
+        shadow_root: entry.shadow_root,
+        shadow: entry.shadow,
+        kind: entry.myself.clone(),
+    })
+}
+
+/// Walks a subtree in tree order, as returned by [`DomTree::descendants`].
+pub struct Descendants<'a> {
+    tree: &'a DomTree,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.following(current);
+        Some(current)
+    }
+}
+
+impl Descendants<'_> {
+    /// The node after `node` in tree order, without leaving the subtree.
+    fn following(&self, node: NodeId) -> Option<NodeId> {
+        let entry = self.tree.get(node)?;
+        if entry.first_child.is_some() {
+            return entry.first_child;
+        }
+        let mut current = node;
+        while current != self.root {
+            let entry = self.tree.get(current)?;
+            if entry.next_sibling.is_some() {
+                return entry.next_sibling;
+            }
+            current = entry.parent?;
+        }
+        None
+    }
+}
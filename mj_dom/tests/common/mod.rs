@@ -0,0 +1,153 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use ecow::EcoString;
+use mj_dom::{nodes::DomSubtree, MjDom, NodeId, DOCUMENT_NODE};
+use stakker::{actor, call, ret_nop, ret_some_do, Actor, ActorOwn, Ret, Stakker};
+use url::Url;
+
+/// A runtime holding a single [`MjDom`], driven by hand so that tests can wait on the parser
+/// thread and on replies to messages.
+pub struct TestDom {
+    pub stakker: Stakker,
+    woken: Arc<AtomicBool>,
+    pub dom: ActorOwn<MjDom>,
+}
+
+impl TestDom {
+    pub fn new() -> Self {
+        let mut stakker = Stakker::new(Instant::now());
+        let woken = Arc::new(AtomicBool::new(false));
+        let waker = woken.clone();
+        stakker.set_poll_waker(move || waker.store(true, Ordering::SeqCst));
+        let dom = actor!(stakker, MjDom::init(), ret_nop!());
+        Self {
+            stakker,
+            woken,
+            dom,
+        }
+    }
+
+    /// Parse `html` and run until the document has finished loading.
+    pub fn load(html: &str) -> Self {
+        let mut test_dom = Self::new();
+        test_dom.parse(html);
+        test_dom
+    }
+
+    /// Parse `html` as a UTF-8 document.
+    pub fn parse(&mut self, html: &str) {
+        self.parse_bytes(html.as_bytes(), Some("text/html; charset=utf-8"));
+    }
+
+    /// Parse `bytes` as if served with `content_type`, running until the document has loaded.
+    pub fn parse_bytes(&mut self, bytes: &[u8], content_type: Option<&str>) {
+        self.fetched(bytes, content_type, None);
+    }
+
+    /// Parse `html` as a UTF-8 document fetched from `url`.
+    pub fn load_from(url: &str, html: &str) -> Self {
+        let mut test_dom = Self::new();
+        let url = Url::parse(url).expect("Test URLs should parse");
+        test_dom.fetched(html.as_bytes(), Some("text/html; charset=utf-8"), Some(url));
+        test_dom
+    }
+
+    fn fetched(&mut self, bytes: &[u8], content_type: Option<&str>, url: Option<Url>) {
+        let content_type = content_type.map(str::to_string);
+        call!(
+            [self.dom],
+            parse_document(bytes.to_vec(), content_type, url)
+        );
+        self.query(|dom, loaded| call!([dom], when_loaded(loaded)))
+    }
+
+    /// Run the runtime until `done` reports true, panicking if that takes unreasonably long.
+    pub fn run_until(&mut self, mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the DOM");
+            self.stakker.run(Instant::now(), false);
+            if self.woken.swap(false, Ordering::SeqCst) {
+                self.stakker.poll_wake();
+            } else {
+                thread::sleep(Duration::from_micros(100));
+            }
+        }
+    }
+
+    /// Run `request` with a [`Ret`] and run the runtime until it is answered.
+    pub fn reply<T: 'static>(&mut self, request: impl FnOnce(Ret<T>)) -> T {
+        let reply = Rc::new(RefCell::new(None));
+        let output = reply.clone();
+        request(ret_some_do!(move |value: T| {
+            *output.borrow_mut() = Some(value);
+        }));
+        self.run_until(|| reply.borrow().is_some());
+        let value = reply.borrow_mut().take().unwrap();
+        value
+    }
+
+    /// Send a message to the [`MjDom`] through `request` and run until its reply arrives.
+    pub fn query<T: 'static>(&mut self, request: impl FnOnce(&Actor<MjDom>, Ret<T>)) -> T {
+        let dom = Actor::clone(&self.dom);
+        self.reply(|reply| request(&dom, reply))
+    }
+
+    pub fn subtree(&mut self) -> DomSubtree {
+        self.query(|dom, subtree| call!([dom], subtree(DOCUMENT_NODE, subtree)))
+    }
+
+    /// The first element matching `selectors` in the document, which has to be there.
+    pub fn find(&mut self, selectors: &str) -> NodeId {
+        self.select(None, selectors)
+    }
+
+    /// The first element matching `selectors` under `root`, which has to be there.
+    pub fn find_in(&mut self, root: NodeId, selectors: &str) -> NodeId {
+        self.select(Some(root), selectors)
+    }
+
+    fn select(&mut self, scope: Option<NodeId>, selectors: &str) -> NodeId {
+        let selectors = selectors.to_string();
+        self.query(|dom, found| call!([dom], query_selector(scope, selectors, found)))
+            .expect("Selector should parse")
+            .expect("Selector should match")
+    }
+
+    pub fn element_by_id(&mut self, id: &str) -> Option<NodeId> {
+        let id = EcoString::from(id);
+        self.query(|dom, found| call!([dom], get_element_by_id(id, found)))
+    }
+
+    /// The element with the id `id`, which has to be there.
+    pub fn by_id(&mut self, id: &str) -> NodeId {
+        self.element_by_id(id).expect("Element should exist")
+    }
+
+    /// The `id` attribute of each node, to compare results by.
+    pub fn ids(&mut self, nodes: Vec<NodeId>) -> Vec<String> {
+        nodes
+            .into_iter()
+            .map(|node| {
+                let id = self.query(|dom, id| call!([dom], get_attribute(node, "id".into(), id)));
+                id.map_or_else(String::new, |id| id.to_string())
+            })
+            .collect()
+    }
+}
+
+/// Parse `html` into a fresh [`MjDom`] and gather the finished document tree.
+pub fn parse(html: &str) -> DomSubtree {
+    TestDom::load(html).subtree()
+}
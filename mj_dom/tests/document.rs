@@ -0,0 +1,63 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::QuirksMode;
+use stakker::call;
+
+fn quirks_mode(html: &str) -> QuirksMode {
+    TestDom::load(html).query(|dom, mode| call!([dom], quirks_mode(mode)))
+}
+
+#[test]
+fn quirks_mode_follows_the_doctype() {
+    assert_eq!(quirks_mode("<!DOCTYPE html><p>"), QuirksMode::NoQuirks);
+    assert_eq!(quirks_mode("<p>No doctype"), QuirksMode::Quirks);
+    assert_eq!(
+        quirks_mode(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd">"#
+        ),
+        QuirksMode::LimitedQuirks
+    );
+}
+
+#[test]
+fn parse_errors_are_reported_with_their_line() {
+    let mut dom = TestDom::load("<!DOCTYPE html>\n<p>\n</div>\n<p>ok");
+    let diagnostics = dom.query(|dom, diagnostics| call!([dom], diagnostics(diagnostics)));
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].line, 3);
+    assert_eq!(diagnostics[0].column, None);
+    assert!(diagnostics[0].to_string().starts_with("line 3: "));
+}
+
+#[test]
+fn entries_record_their_source_line() {
+    let mut dom = TestDom::load("<!DOCTYPE html>\n<title>x</title>\n\n<p>y");
+    let tree = dom.subtree();
+    let html = &tree.children[1];
+    let paragraph = html.children[1].children[0].id;
+    let line = dom.query(|dom, line| call!([dom], source_line(paragraph, line)));
+    assert_eq!(line, Some(4));
+}
+
+#[test]
+fn quirks_mode_is_reset_between_documents() {
+    let mut dom = TestDom::load("<p>No doctype");
+    dom.parse("<!DOCTYPE html><p>");
+    let mode = dom.query(|dom, mode| call!([dom], quirks_mode(mode)));
+    assert_eq!(mode, QuirksMode::NoQuirks);
+}
+
+#[test]
+fn parsing_a_new_document_replaces_the_old_tree() {
+    let mut dom = TestDom::load("<!DOCTYPE html><title>first</title><p>one<p>two");
+    let old_paragraph = dom.subtree().children[1].children[1].children[0].id;
+
+    dom.parse("<!DOCTYPE html><title>second</title>");
+    let tree = dom.subtree();
+    let body = &tree.children[1].children[1];
+    assert!(body.children.is_empty());
+    // Ids start over with each document, and this one is too small to reuse the paragraph's
+    let found = dom.query(|dom, found| call!([dom], contains(old_paragraph, found)));
+    assert!(!found);
+}
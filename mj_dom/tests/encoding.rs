@@ -0,0 +1,98 @@
+mod common;
+
+use common::TestDom;
+use encoding_rs::{UTF_16LE, UTF_8, WINDOWS_1252};
+use mj_dom::{
+    encoding::{sniff_encoding, sniff_xml_encoding},
+    nodes::MemberKind,
+    Encoding,
+};
+use stakker::call;
+
+fn encoding(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    let mut dom = TestDom::new();
+    dom.parse_bytes(bytes, content_type);
+    dom.query(|dom, encoding| call!([dom], encoding(encoding)))
+}
+
+#[test]
+fn byte_order_mark_wins_over_everything_else() {
+    let bytes = b"\xEF\xBB\xBF<meta charset=windows-1252><p>";
+    assert_eq!(
+        sniff_encoding(bytes, Some("text/html; charset=koi8-r")),
+        UTF_8
+    );
+    assert_eq!(sniff_encoding(b"\xFF\xFE<\0p\0>\0", None), UTF_16LE);
+}
+
+#[test]
+fn content_type_charset_wins_over_meta() {
+    let bytes = b"<meta charset=utf-8><p>";
+    let encoding = sniff_encoding(bytes, Some(r#"text/html; charset="Shift_JIS""#));
+    assert_eq!(encoding.name(), "Shift_JIS");
+    // An unknown label is ignored rather than trusted
+    assert_eq!(
+        sniff_encoding(bytes, Some("text/html; charset=nonsense")),
+        UTF_8
+    );
+}
+
+#[test]
+fn meta_declarations_are_prescanned() {
+    assert_eq!(
+        sniff_encoding(b"<!-- <meta charset=gbk> --><META CHARSET='utf-8'>", None),
+        UTF_8
+    );
+    let pragma = br#"<meta http-equiv="Content-Type" content="text/html; charset=iso-8859-2">"#;
+    assert_eq!(sniff_encoding(pragma, None).name(), "ISO-8859-2");
+    // A content attribute only counts alongside the http-equiv pragma
+    let no_pragma = br#"<meta content="text/html; charset=iso-8859-2">"#;
+    assert_eq!(sniff_encoding(no_pragma, None), WINDOWS_1252);
+    // UTF-16 can't be declared from inside the document it would decode
+    assert_eq!(sniff_encoding(b"<meta charset=utf-16le>", None), UTF_8);
+}
+
+#[test]
+fn undeclared_documents_fall_back_to_windows_1252() {
+    assert_eq!(sniff_encoding(b"<p>Caf\xE9", None), WINDOWS_1252);
+    assert_eq!(
+        sniff_encoding(b"<p>Caf\xE9", Some("text/html")),
+        WINDOWS_1252
+    );
+}
+
+#[test]
+fn documents_are_decoded_with_the_sniffed_encoding() {
+    let mut dom = TestDom::new();
+    dom.parse_bytes(b"<p>Caf\xE9", None);
+    let tree = dom.subtree();
+    let paragraph = &tree.children[0].children[1].children[0];
+    let MemberKind::Text { contents } = &paragraph.children[0].kind else {
+        panic!("Expected text in the paragraph");
+    };
+    assert_eq!(contents, "Café");
+    assert_eq!(
+        dom.query(|dom, encoding| call!([dom], encoding(encoding))),
+        WINDOWS_1252
+    );
+}
+
+#[test]
+fn documents_report_the_encoding_they_were_served_with() {
+    assert_eq!(
+        encoding("<p>Café".as_bytes(), Some("text/html; charset=utf-8")),
+        UTF_8
+    );
+}
+
+#[test]
+fn xml_declarations_name_the_encoding_and_default_to_utf_8() {
+    let declared = br#"<?xml version="1.0" encoding='ISO-8859-2'?><root/>"#;
+    assert_eq!(sniff_xml_encoding(declared, None).name(), "ISO-8859-2");
+    assert_eq!(
+        sniff_xml_encoding(declared, Some("application/xml; charset=utf-8")),
+        UTF_8
+    );
+    // XML has no meta prescan and no windows-1252 fallback
+    assert_eq!(sniff_xml_encoding(b"<meta charset=gbk/>", None), UTF_8);
+}
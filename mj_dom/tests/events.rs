@@ -0,0 +1,179 @@
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use common::TestDom;
+use ecow::EcoString;
+use mj_dom::{
+    events::{DispatchedEvent, Event, ListenerId, MouseEvent},
+    NodeId,
+};
+use stakker::{actor, call, fwd_do, fwd_to, ret_nop, Fwd, CX};
+
+const PAGE: &str = "<!DOCTYPE html><div id=outer><p id=inner><b id=target>click</b></p></div>";
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+fn listen(
+    dom: &mut TestDom,
+    node: NodeId,
+    event_type: &str,
+    capture: bool,
+    listener: Fwd<DispatchedEvent>,
+) -> ListenerId {
+    let event_type = EcoString::from(event_type);
+    dom.query(|dom, id| {
+        call!(
+            [dom],
+            add_event_listener(node, event_type, capture, listener, id)
+        )
+    })
+}
+
+/// A listener noting down `label` and the phase it was called in.
+fn logger(log: &Log, label: &str) -> Fwd<DispatchedEvent> {
+    let (log, label) = (log.clone(), label.to_string());
+    fwd_do!(move |event: DispatchedEvent| {
+        log.borrow_mut()
+            .push(format!("{} {:?}", label, event.phase));
+    })
+}
+
+fn dispatch(dom: &mut TestDom, target: NodeId, event: Event) -> bool {
+    dom.query(|dom, done| call!([dom], dispatch_event(target, event, done)))
+}
+
+fn click() -> Event {
+    Event::mouse("click", MouseEvent::default())
+}
+
+#[test]
+fn events_capture_down_and_bubble_up_the_path() {
+    let mut dom = TestDom::load(PAGE);
+    let (outer, target) = (dom.by_id("outer"), dom.by_id("target"));
+    let log = Log::default();
+    listen(&mut dom, outer, "click", false, logger(&log, "outer"));
+    listen(&mut dom, outer, "click", true, logger(&log, "outer"));
+    listen(&mut dom, target, "click", false, logger(&log, "target"));
+    listen(&mut dom, target, "click", true, logger(&log, "target"));
+    listen(&mut dom, outer, "keydown", true, logger(&log, "keys"));
+
+    assert!(dispatch(&mut dom, target, click()));
+    assert_eq!(
+        *log.borrow(),
+        [
+            "outer Capturing",
+            "target AtTarget",
+            "target AtTarget",
+            "outer Bubbling"
+        ]
+    );
+}
+
+#[test]
+fn stopping_propagation_finishes_the_current_node_first() {
+    let mut dom = TestDom::load(PAGE);
+    let (outer, target) = (dom.by_id("outer"), dom.by_id("target"));
+    let log = Log::default();
+    let stopper = fwd_do!(|event: DispatchedEvent| event.stop_propagation());
+    listen(&mut dom, target, "click", false, stopper);
+    listen(&mut dom, target, "click", false, logger(&log, "target"));
+    listen(&mut dom, outer, "click", false, logger(&log, "outer"));
+
+    dispatch(&mut dom, target, click());
+    assert_eq!(*log.borrow(), ["target AtTarget"]);
+}
+
+#[test]
+fn capturing_at_the_target_can_stop_its_other_listeners() {
+    let mut dom = TestDom::load(PAGE);
+    let target = dom.by_id("target");
+    let log = Log::default();
+    // Registered first, but the capturing listeners still run before it and stop the event
+    listen(&mut dom, target, "click", false, logger(&log, "bubble"));
+    let stopper = fwd_do!(|event: DispatchedEvent| event.stop_propagation());
+    listen(&mut dom, target, "click", true, stopper);
+    listen(&mut dom, target, "click", true, logger(&log, "capture"));
+
+    dispatch(&mut dom, target, click());
+    assert_eq!(*log.borrow(), ["capture AtTarget"]);
+}
+
+#[test]
+fn stopping_immediate_propagation_skips_the_remaining_listeners() {
+    let mut dom = TestDom::load(PAGE);
+    let target = dom.by_id("target");
+    let log = Log::default();
+    let stopper = fwd_do!(|event: DispatchedEvent| event.stop_immediate_propagation());
+    listen(&mut dom, target, "click", false, stopper);
+    listen(&mut dom, target, "click", false, logger(&log, "target"));
+
+    dispatch(&mut dom, target, click());
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn preventing_the_default_only_applies_to_cancelable_events() {
+    let mut dom = TestDom::load(PAGE);
+    let (inner, target) = (dom.by_id("inner"), dom.by_id("target"));
+    let canceller = || fwd_do!(|event: DispatchedEvent| event.prevent_default());
+    listen(&mut dom, inner, "click", false, canceller());
+    listen(&mut dom, target, "focus", false, canceller());
+
+    assert!(!dispatch(&mut dom, target, click()));
+    assert!(dispatch(&mut dom, target, Event::focus("focus", None)));
+}
+
+#[test]
+fn events_that_dont_bubble_are_still_captured() {
+    let mut dom = TestDom::load(PAGE);
+    let (outer, target) = (dom.by_id("outer"), dom.by_id("target"));
+    let log = Log::default();
+    listen(&mut dom, outer, "focus", true, logger(&log, "capture"));
+    listen(&mut dom, outer, "focus", false, logger(&log, "bubble"));
+
+    dispatch(&mut dom, target, Event::focus("focus", None));
+    assert_eq!(*log.borrow(), ["capture Capturing"]);
+}
+
+#[test]
+fn removed_listeners_are_not_called() {
+    let mut dom = TestDom::load(PAGE);
+    let target = dom.by_id("target");
+    let log = Log::default();
+    let id = listen(&mut dom, target, "click", false, logger(&log, "removed"));
+    call!([dom.dom], remove_event_listener(target, id));
+
+    dispatch(&mut dom, target, click());
+    assert!(log.borrow().is_empty());
+}
+
+/// A listener living in an actor of its own, so that it only runs once its message comes up.
+struct Stopper {
+    calls: Log,
+}
+
+impl Stopper {
+    fn init(_: CX![], calls: Log) -> Option<Self> {
+        Some(Self { calls })
+    }
+
+    fn handle(&mut self, _: CX![], event: DispatchedEvent) {
+        self.calls.borrow_mut().push("stopper".to_string());
+        event.stop_propagation();
+    }
+}
+
+#[test]
+fn actor_listeners_run_before_propagation_continues() {
+    let mut dom = TestDom::load(PAGE);
+    let (inner, target) = (dom.by_id("inner"), dom.by_id("target"));
+    let log = Log::default();
+    let stopper = actor!(dom.stakker, Stopper::init(log.clone()), ret_nop!());
+    let handle = fwd_to!([stopper], handle() as (DispatchedEvent));
+    listen(&mut dom, target, "click", false, handle);
+    listen(&mut dom, inner, "click", false, logger(&log, "inner"));
+
+    dispatch(&mut dom, target, click());
+    assert_eq!(*log.borrow(), ["stopper"]);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::TestDom;
+use html5ever::{namespace_url, ns, LocalName};
+use mj_dom::nodes::element::ElementNamespace;
+use stakker::call;
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<div id=html>
+  <svg id=svg viewbox="0 0 10 10"><clippath id=clip></clippath><use id=use xlink:href="#clip"/></svg>
+  <math id=math definitionurl=/def><mi id=mi>x</mi></math>
+</div>"##;
+
+fn namespace_of(dom: &mut TestDom, selectors: &str) -> Option<ElementNamespace> {
+    let node = dom.find(selectors);
+    dom.query(|dom, entry| call!([dom], kind(node, entry)))
+        .element_namespace()
+}
+
+#[test]
+fn foreign_elements_keep_their_namespaces() {
+    let mut dom = TestDom::load(PAGE);
+    assert_eq!(
+        namespace_of(&mut dom, "#html"),
+        Some(ElementNamespace::Html)
+    );
+    assert_eq!(namespace_of(&mut dom, "#svg"), Some(ElementNamespace::Svg));
+    assert_eq!(
+        namespace_of(&mut dom, "#math"),
+        Some(ElementNamespace::MathMl)
+    );
+    assert_eq!(
+        namespace_of(&mut dom, "#mi"),
+        Some(ElementNamespace::MathMl)
+    );
+
+    let clip = dom.find("#clip");
+    let local = dom.query(|dom, name| call!([dom], local_name(clip, name)));
+    assert_eq!(
+        local.as_deref(),
+        Some("clipPath"),
+        "Tag names are case adjusted"
+    );
+    let namespace = dom.query(|dom, namespace| call!([dom], namespace_uri(clip, namespace)));
+    assert_eq!(namespace, Some(ns!(svg)));
+}
+
+#[test]
+fn tag_names_are_only_uppercased_for_html() {
+    let mut dom = TestDom::load(PAGE);
+    let (html, clip) = (dom.find("#html"), dom.find("#clip"));
+    assert_eq!(
+        dom.query(|dom, name| call!([dom], tag_name(html, name))),
+        Some("DIV".to_string())
+    );
+    assert_eq!(
+        dom.query(|dom, name| call!([dom], tag_name(clip, name))),
+        Some("clipPath".to_string())
+    );
+}
+
+#[test]
+fn foreign_attributes_are_adjusted() {
+    let mut dom = TestDom::load(PAGE);
+    let (svg, using, math) = (dom.find("#svg"), dom.find("#use"), dom.find("#math"));
+    let get_ns = |dom: &mut TestDom, node, local: &str| {
+        let local = LocalName::from(local);
+        dom.query(|dom, value| call!([dom], get_attribute_ns(node, ns!(), local, value)))
+    };
+    assert_eq!(
+        get_ns(&mut dom, svg, "viewBox").as_deref(),
+        Some("0 0 10 10")
+    );
+    assert_eq!(get_ns(&mut dom, svg, "viewbox"), None);
+    assert_eq!(
+        get_ns(&mut dom, math, "definitionURL").as_deref(),
+        Some("/def")
+    );
+
+    let href = dom.query(|dom, value| {
+        call!(
+            [dom],
+            get_attribute_ns(using, ns!(xlink), LocalName::from("href"), value)
+        )
+    });
+    assert_eq!(href.as_deref(), Some("#clip"));
+    let href =
+        dom.query(|dom, value| call!([dom], get_attribute(using, "xlink:href".into(), value)));
+    assert_eq!(href.as_deref(), Some("#clip"));
+
+    // Selectors match foreign names in their adjusted case too
+    assert_eq!(dom.find("svg clipPath"), dom.find("#clip"));
+    assert_eq!(dom.find("[viewBox]"), svg);
+
+    let html = dom.query(|dom, html| call!([dom], outer_html(using, html)));
+    assert_eq!(html, r##"<use id="use" xlink:href="#clip"></use>"##);
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::{forms::FormSubmission, NodeId};
+use stakker::call;
+
+const PAGE_URL: &str = "https://example.com/page?old";
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<form id=search action=/search>
+  <input name=q value="two words">
+  <input type=checkbox name=safe checked>
+  <input type=checkbox name=off>
+  <input name=gone value=x disabled>
+  <fieldset disabled><input name=fenced value=x></fieldset>
+  <select name=lang><option>en<option selected value=fr>French</select>
+  <textarea name=notes>line one
+line two</textarea>
+  <input type=image id=go name=go>
+  <button id=send name=send value=yes>Send</button>
+  <button id=other name=other>Other</button>
+</form>
+<input id=outside form=search name=extra value=1>
+<input id=loose name=loose>
+<form id=upload method=post enctype=multipart/form-data action="upload?ignored">
+  <input name="a&quot;b" value=1>
+  <input type=file name=file>
+</form>"#;
+
+fn submit(dom: &mut TestDom, form: NodeId, submitter: Option<NodeId>) -> FormSubmission {
+    dom.query(|dom, done| call!([dom], submit_form(form, submitter, done)))
+        .expect("Form should submit")
+}
+
+#[test]
+fn controls_belong_to_the_form_around_them_or_the_one_they_name() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let search = dom.find("#search");
+    for (selectors, owner) in [
+        ("#send", Some(search)),
+        ("#outside", Some(search)),
+        ("#loose", None),
+    ] {
+        let node = dom.find(selectors);
+        let found = dom.query(|dom, owner| call!([dom], form_owner(node, owner)));
+        assert_eq!(found, owner, "{}", selectors);
+    }
+}
+
+#[test]
+fn the_parser_keeps_controls_with_a_form_it_closed() {
+    // The form element ends with the cell, but the input after the table still belongs to it
+    let mut dom = TestDom::load_from(
+        PAGE_URL,
+        "<table><tr><td><form id=f><input id=inside></td></tr></table><input id=after>",
+    );
+    let form = dom.find("#f");
+    let after = dom.find("#after");
+    let owner = dom.query(|dom, owner| call!([dom], form_owner(after, owner)));
+    assert_eq!(owner, Some(form));
+
+    // Taking the form out of the document leaves the input behind without one
+    let result = dom.query(|dom, done| call!([dom], remove(form, done)));
+    assert_eq!(result, Ok(()));
+    let owner = dom.query(|dom, owner| call!([dom], form_owner(after, owner)));
+    assert_eq!(owner, None);
+}
+
+#[test]
+fn elements_are_listed_in_tree_order_without_image_buttons() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let search = dom.find("#search");
+    let elements = dom.query(|dom, elements| call!([dom], form_elements(search, elements)));
+    assert_eq!(elements.len(), 11);
+    assert!(!elements.contains(&dom.find("#go")));
+    assert_eq!(elements.last(), Some(&dom.find("#outside")));
+}
+
+#[test]
+fn get_replaces_the_query_of_the_action() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let (search, send) = (dom.find("#search"), dom.find("#send"));
+    let submission = submit(&mut dom, search, Some(send));
+    assert_eq!(
+        submission.url().as_str(),
+        "https://example.com/search?q=two+words&safe=on&lang=fr\
+         &notes=line+one%0D%0Aline+two&send=yes&extra=1"
+    );
+}
+
+#[test]
+fn image_buttons_submit_the_click_coordinates() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let (search, go) = (dom.find("#search"), dom.find("#go"));
+    let FormSubmission::Get { url } = submit(&mut dom, search, Some(go)) else {
+        panic!("Form should submit with GET");
+    };
+    assert!(url.query().unwrap().contains("&go.x=0&go.y=0&"));
+}
+
+#[test]
+fn post_can_encode_as_multipart() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let upload = dom.find("#upload");
+    let FormSubmission::Post {
+        url,
+        content_type,
+        body,
+    } = submit(&mut dom, upload, None)
+    else {
+        panic!("Form should submit with POST");
+    };
+    assert_eq!(url.as_str(), "https://example.com/upload?ignored");
+    let boundary = content_type
+        .strip_prefix("multipart/form-data; boundary=")
+        .expect("Content type should carry the boundary");
+    let expected = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"a%22b\"\r\n\r\n1\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n\r\n--{boundary}--\r\n"
+    );
+    assert_eq!(String::from_utf8(body).unwrap(), expected);
+}
+
+#[test]
+fn post_can_encode_as_text_or_in_another_charset() {
+    let mut dom = TestDom::load_from(
+        PAGE_URL,
+        "<form id=plain method=post enctype=text/plain><input name=a value='x y'>\
+         <textarea name=b>1\r2</textarea></form>\
+         <form id=latin method=post accept-charset=latin1><input name=c value=é></form>",
+    );
+    let plain = dom.find("#plain");
+    let FormSubmission::Post {
+        content_type, body, ..
+    } = submit(&mut dom, plain, None)
+    else {
+        panic!("Form should submit with POST");
+    };
+    assert_eq!(content_type, "text/plain");
+    assert_eq!(body, b"a=x y\r\nb=1\r\n2\r\n");
+
+    let latin = dom.find("#latin");
+    let FormSubmission::Post {
+        url,
+        content_type,
+        body,
+    } = submit(&mut dom, latin, None)
+    else {
+        panic!("Form should submit with POST");
+    };
+    assert_eq!(content_type, "application/x-www-form-urlencoded");
+    assert_eq!(body, b"c=%E9");
+    assert_eq!(
+        url.as_str(),
+        PAGE_URL,
+        "Forms without an action submit to the document"
+    );
+}
+
+#[test]
+fn submitters_have_to_be_submit_buttons_of_the_form() {
+    let mut dom = TestDom::load_from(PAGE_URL, PAGE);
+    let (upload, send) = (dom.find("#upload"), dom.find("#send"));
+    let result = dom.query(|dom, done| call!([dom], submit_form(upload, Some(send), done)));
+    assert_eq!(result, Err(mj_dom::error::DomError::NotFound));
+}
@@ -0,0 +1,269 @@
+//! Runs the html5lib-tests tree-construction format against [`mj_dom::MjDom`].
+//!
+//! Every `.dat` file under `tests/fixtures/html5lib` is split into cases, each case is parsed into
+//! a fresh DOM and the resulting tree is dumped in the `| <tag>` notation used by html5lib-tests.
+//! Cases listed in `tests/fixtures/html5lib/known_failures` are expected to fail; the test fails if
+//! any other case fails, or if a known failure starts passing and should be removed from the list.
+//!
+//! The `.dat` files hold a selection of cases under the names of the html5lib-tests files they
+//! cover, not the complete upstream files. Those can be dropped in unmodified in their place, with
+//! `known_failures` updated to match.
+
+mod common;
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use common::TestDom;
+use html5ever::{namespace_url, ns, QualName};
+use mj_dom::{
+    nodes::{DomSubtree, MemberKind},
+    NodeId,
+};
+use stakker::call;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/html5lib");
+
+#[derive(Debug, Default)]
+struct TreeConstructionCase {
+    name: String,
+    data: String,
+    fragment_context: Option<String>,
+    scripting_disabled: bool,
+    document: String,
+}
+
+/// Split a `.dat` file into its cases. Only the sections needed to build and compare the tree are
+/// kept; `#errors` is ignored since html5ever reports errors in its own words.
+fn parse_dat(file_name: &str, contents: &str) -> Vec<TreeConstructionCase> {
+    fn finish(case: &mut TreeConstructionCase, cases: &mut Vec<TreeConstructionCase>) {
+        if !case.name.is_empty() {
+            let mut case = std::mem::take(case);
+            case.data.pop();
+            cases.push(case);
+        }
+    }
+
+    let mut cases = vec![];
+    let mut case = TreeConstructionCase::default();
+    let mut section = "";
+    for line in contents.lines() {
+        match line {
+            "#data" => {
+                finish(&mut case, &mut cases);
+                case.name = format!("{}#{}", file_name, cases.len() + 1);
+                section = line;
+                continue;
+            }
+            "#script-off" => {
+                case.scripting_disabled = true;
+                continue;
+            }
+            "#errors" | "#new-errors" | "#document-fragment" | "#document" | "#script-on" => {
+                section = line;
+                continue;
+            }
+            _ => {}
+        }
+        match section {
+            "#data" => {
+                case.data.push_str(line);
+                case.data.push('\n');
+            }
+            "#document-fragment" => case.fragment_context = Some(line.to_string()),
+            "#document" if !line.is_empty() => {
+                case.document.push_str(line);
+                case.document.push('\n');
+            }
+            _ => {}
+        }
+    }
+    finish(&mut case, &mut cases);
+    cases
+}
+
+fn qualified_name(name: &QualName) -> String {
+    if name.ns == ns!(svg) {
+        format!("svg {}", name.local)
+    } else if name.ns == ns!(mathml) {
+        format!("math {}", name.local)
+    } else {
+        name.local.to_string()
+    }
+}
+
+fn attribute_name(name: &QualName) -> String {
+    if name.ns == ns!(xlink) {
+        format!("xlink {}", name.local)
+    } else if name.ns == ns!(xml) {
+        format!("xml {}", name.local)
+    } else if name.ns == ns!(xmlns) {
+        format!("xmlns {}", name.local)
+    } else {
+        name.local.to_string()
+    }
+}
+
+fn dump_node(node: &DomSubtree, depth: usize, output: &mut String) {
+    let indent = "  ".repeat(depth);
+    match &node.kind {
+        MemberKind::Document | MemberKind::DocumentFragment => {}
+        MemberKind::Doctype {
+            name,
+            public_id,
+            system_id,
+        } => {
+            if public_id.is_empty() && system_id.is_empty() {
+                output.push_str(&format!("| {}<!DOCTYPE {}>\n", indent, name));
+            } else {
+                output.push_str(&format!(
+                    "| {}<!DOCTYPE {} \"{}\" \"{}\">\n",
+                    indent, name, public_id, system_id
+                ));
+            }
+        }
+        MemberKind::Element { name, attrs } => {
+            output.push_str(&format!("| {}<{}>\n", indent, qualified_name(name)));
+            let mut attrs = attrs
+                .iter()
+                .map(|(name, value)| (attribute_name(name), value))
+                .collect::<Vec<_>>();
+            attrs.sort();
+            for (name, value) in attrs {
+                output.push_str(&format!("| {}  {}=\"{}\"\n", indent, name, value));
+            }
+        }
+        MemberKind::Comment { content } => {
+            output.push_str(&format!("| {}<!-- {} -->\n", indent, content));
+        }
+        MemberKind::Text { contents } => {
+            output.push_str(&format!("| {}\"{}\"\n", indent, contents));
+        }
+        MemberKind::ProcessingInstruction { target, data } => {
+            output.push_str(&format!("| {}<?{} {}>\n", indent, target, data));
+        }
+    }
+
+    if let Some(contents) = &node.template_contents {
+        output.push_str(&format!("| {}  content\n", indent));
+        dump_node(contents, depth + 2, output);
+    }
+
+    let depth = match node.kind {
+        MemberKind::Document | MemberKind::DocumentFragment => depth,
+        _ => depth + 1,
+    };
+    for child in &node.children {
+        dump_node(child, depth, output);
+    }
+}
+
+fn dump(tree: &DomSubtree) -> String {
+    let mut output = String::new();
+    dump_node(tree, 0, &mut output);
+    output
+}
+
+fn find_element(node: &DomSubtree, local_name: &str) -> Option<NodeId> {
+    match &node.kind {
+        MemberKind::Element { name, .. } if name.ns == ns!(html) && &*name.local == local_name => {
+            Some(node.id)
+        }
+        _ => node
+            .children
+            .iter()
+            .find_map(|child| find_element(child, local_name)),
+    }
+}
+
+/// Parse `data` as the contents of a `context` element and dump the result, the way html5lib-tests
+/// dumps fragments: the context's children, starting from the outermost indentation.
+fn parse_fragment(context: &str, data: &str) -> Result<String, String> {
+    if context.contains(' ') {
+        return Err("fragment contexts outside the HTML namespace are not supported".to_string());
+    }
+    let mut dom = TestDom::load(&format!("<!DOCTYPE html><{}>", context));
+    let element = find_element(&dom.subtree(), context)
+        .ok_or_else(|| format!("could not create a {} element for the context", context))?;
+    dom.query(|dom, done| call!([dom], set_inner_html(element, data.to_string(), done)))
+        .map_err(|error| error.to_string())?;
+
+    let tree = dom.query(|dom, subtree| call!([dom], subtree(element, subtree)));
+    let fragment = tree.template_contents.as_deref().unwrap_or(&tree);
+    let mut output = String::new();
+    for child in &fragment.children {
+        dump_node(child, 0, &mut output);
+    }
+    Ok(output)
+}
+
+fn run_case(case: &TreeConstructionCase) -> Result<(), String> {
+    if case.scripting_disabled {
+        return Err("parsing with scripting disabled is not supported".to_string());
+    }
+
+    let data = case.data.clone();
+    let context = case.fragment_context.clone();
+    let actual = panic::catch_unwind(AssertUnwindSafe(move || match context {
+        Some(context) => parse_fragment(&context, &data),
+        None => Ok(dump(&common::parse(&data))),
+    }))
+    .map_err(|_| "parsing panicked".to_string())??;
+    if actual == case.document {
+        Ok(())
+    } else {
+        Err(format!("expected:\n{}actual:\n{}", case.document, actual))
+    }
+}
+
+#[test]
+fn tree_construction() {
+    let known_failures =
+        fs::read_to_string(Path::new(FIXTURES).join("known_failures")).unwrap_or_default();
+    let known_failures = known_failures
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>();
+
+    let mut files = fs::read_dir(FIXTURES)
+        .expect("Could not read html5lib fixtures")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dat"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let (mut passed, mut failed, mut regressions) = (0, 0, vec![]);
+    for path in files {
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let contents = fs::read_to_string(&path).unwrap();
+        for case in parse_dat(&file_name, &contents) {
+            let expected_failure = known_failures.contains(&case.name.as_str());
+            match run_case(&case) {
+                Ok(()) => {
+                    passed += 1;
+                    println!("PASS {}", case.name);
+                    if expected_failure {
+                        regressions.push(format!(
+                            "{} passes but is listed as a known failure",
+                            case.name
+                        ));
+                    }
+                }
+                Err(reason) => {
+                    failed += 1;
+                    println!("FAIL {}\n{}\n{}", case.name, case.data, reason);
+                    if !expected_failure {
+                        regressions.push(format!("{} fails", case.name));
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    assert!(regressions.is_empty(), "{}", regressions.join("\n"));
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use common::TestDom;
+use ecow::EcoString;
+use html5ever::{namespace_url, ns, LocalName, Namespace};
+use stakker::call;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<div id=main class="panel wide">
+  <p id=intro class=panel>Hello</p>
+  <svg><clipPath id=clip class=panel></clipPath></svg>
+</div>
+<template><p id=hidden class=panel></p></template>
+<p id=outro></p>"#;
+
+fn by_class(dom: &mut TestDom, class_names: &str) -> Vec<String> {
+    let class_names = EcoString::from(class_names);
+    let found =
+        dom.query(|dom, found| call!([dom], get_elements_by_class_name(class_names, found)));
+    dom.ids(found)
+}
+
+fn by_tag(dom: &mut TestDom, name: &str) -> Vec<String> {
+    let name = EcoString::from(name);
+    let found = dom.query(|dom, found| call!([dom], get_elements_by_tag_name(name, found)));
+    dom.ids(found)
+}
+
+#[test]
+fn parsed_elements_are_indexed() {
+    let mut dom = TestDom::load(PAGE);
+    let intro = dom.element_by_id("intro").expect("Indexed by id");
+    assert_eq!(dom.ids(vec![intro]), ["intro"]);
+    assert!(dom.element_by_id("missing").is_none());
+
+    assert_eq!(by_class(&mut dom, "panel"), ["main", "intro", "clip"]);
+    assert_eq!(by_class(&mut dom, " wide  panel "), ["main"]);
+    assert_eq!(by_class(&mut dom, ""), Vec::<String>::new());
+
+    assert_eq!(by_tag(&mut dom, "P"), ["intro", "outro"]);
+    assert_eq!(by_tag(&mut dom, "clipPath"), ["clip"]);
+    assert_eq!(by_tag(&mut dom, "clippath"), Vec::<String>::new());
+    assert_eq!(by_tag(&mut dom, "*").len(), 9);
+}
+
+#[test]
+fn template_contents_are_left_out() {
+    let mut dom = TestDom::load(PAGE);
+    assert!(dom.element_by_id("hidden").is_none());
+}
+
+#[test]
+fn attribute_changes_update_the_index() {
+    let mut dom = TestDom::load(PAGE);
+    let intro = dom.element_by_id("intro").unwrap();
+    let result = dom.query(|dom, done| {
+        call!(
+            [dom],
+            set_attribute(intro, "id".into(), "greeting".into(), done)
+        )
+    });
+    assert_eq!(result, Ok(()));
+    call!([dom.dom], remove_attribute(intro, "class".into()));
+
+    assert!(dom.element_by_id("intro").is_none());
+    assert!(dom.element_by_id("greeting").is_some());
+    assert_eq!(by_class(&mut dom, "panel"), ["main", "clip"]);
+}
+
+#[test]
+fn inner_html_swaps_indexed_children() {
+    let mut dom = TestDom::load(PAGE);
+    let main = dom.element_by_id("main").unwrap();
+    let result = dom.query(|dom, done| {
+        call!(
+            [dom],
+            set_inner_html(main, "<span id=fresh class=panel></span>".to_string(), done)
+        )
+    });
+    assert_eq!(result, Ok(()));
+
+    assert!(dom.element_by_id("intro").is_none());
+    assert!(dom.element_by_id("fresh").is_some());
+    assert_eq!(by_class(&mut dom, "panel"), ["main", "fresh"]);
+}
+
+#[test]
+fn quirks_mode_class_names_ignore_case() {
+    let mut dom = TestDom::load("<div id=box class=Panel></div>");
+    assert_eq!(by_class(&mut dom, "PANEL"), ["box"]);
+}
+
+#[test]
+fn results_follow_tree_order_rather_than_creation() {
+    let mut dom = TestDom::load("<div id=host></div><p id=dup class=k>first</p>");
+    let host = dom.element_by_id("host").unwrap();
+    let result = dom.query(|dom, done| {
+        call!(
+            [dom],
+            set_inner_html(host, "<span id=dup class=k>second</span>".to_string(), done)
+        )
+    });
+    assert_eq!(result, Ok(()));
+
+    let span = dom.query(|dom, found| call!([dom], get_elements_by_tag_name("span".into(), found)));
+    assert_eq!(dom.element_by_id("dup"), span.first().copied());
+    let found = dom.query(|dom, found| call!([dom], get_elements_by_class_name("k".into(), found)));
+    assert_eq!(found[0], span[0]);
+}
+
+#[test]
+fn long_sibling_lists_are_put_in_order_in_one_walk() {
+    // Comparing nodes pairwise scans sibling lists over and over, which would take long enough
+    // here to run into the time limit of the test DOM
+    let items = (0..20_000)
+        .map(|i| format!("<li id=i{i}>"))
+        .collect::<String>();
+    let mut dom = TestDom::load(&format!("<ul>{items}</ul>"));
+    let found = dom.query(|dom, found| call!([dom], get_elements_by_tag_name("li".into(), found)));
+    assert_eq!(found.len(), 20_000);
+    let ends = dom.ids(vec![found[0], found[19_999]]);
+    assert_eq!(ends, ["i0", "i19999"]);
+}
+
+fn by_tag_ns(dom: &mut TestDom, namespace: Namespace, local_name: &str) -> Vec<String> {
+    let local_name = LocalName::from(local_name);
+    let found = dom.query(|dom, found| {
+        call!(
+            [dom],
+            get_elements_by_tag_name_ns(namespace, local_name, found)
+        )
+    });
+    dom.ids(found)
+}
+
+#[test]
+fn namespaced_lookups_tell_html_and_svg_apart() {
+    let mut dom = TestDom::load(
+        r#"<a id=link></a>
+        <svg id=pic><a id=shape></a><title id=label></title></svg>
+        <title id=heading></title>
+        <A id=upper></A>"#,
+    );
+    assert_eq!(by_tag_ns(&mut dom, ns!(html), "a"), ["link", "upper"]);
+    assert_eq!(by_tag_ns(&mut dom, ns!(svg), "a"), ["shape"]);
+    assert_eq!(
+        by_tag_ns(&mut dom, "*".into(), "a"),
+        ["link", "shape", "upper"]
+    );
+    assert_eq!(
+        by_tag_ns(&mut dom, ns!(svg), "*"),
+        ["pic", "shape", "label"]
+    );
+    assert_eq!(by_tag_ns(&mut dom, ns!(html), "title"), ["heading"]);
+    assert_eq!(by_tag_ns(&mut dom, ns!(), "a"), Vec::<String>::new());
+    // Local names are compared as they are, without lowercasing for HTML elements
+    assert_eq!(by_tag_ns(&mut dom, ns!(html), "A"), Vec::<String>::new());
+
+    let shape = dom.by_id("shape");
+    dom.query(|dom, done| call!([dom], remove(shape, done)))
+        .expect("Node should exist");
+    assert_eq!(by_tag_ns(&mut dom, "*".into(), "a"), ["link", "upper"]);
+}
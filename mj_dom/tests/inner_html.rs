@@ -0,0 +1,53 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::{error::DomError, nodes::MemberKind};
+use stakker::call;
+
+#[test]
+fn inner_html_replaces_the_children() {
+    let mut dom = TestDom::load("<!DOCTYPE html><div id=panel><p>old</p></div><p>after");
+    let body = &dom.subtree().children[1].children[1];
+    let panel = body.children[0].id;
+    let old_paragraph = body.children[0].children[0].id;
+    // Replaced children are released at the end of the turn unless something holds on to them
+    let retained = dom.query(|dom, retained| call!([dom], retain(old_paragraph, retained)));
+
+    let result = dom.query(|dom, done| {
+        call!(
+            [dom],
+            set_inner_html(panel, "<b>new</b> text".to_string(), done)
+        )
+    });
+    assert_eq!(result, Ok(()));
+
+    let body = &dom.subtree().children[1].children[1];
+    let MemberKind::Element { name, .. } = &body.children[0].children[0].kind else {
+        panic!("Expected the parsed element first");
+    };
+    assert_eq!(&*name.local, "b");
+    assert_eq!(body.children[0].children.len(), 2);
+    assert_eq!(
+        body.children.len(),
+        2,
+        "Siblings of the target are untouched"
+    );
+
+    let old_parent = dom.query(|dom, parent| call!([dom], parent(old_paragraph, parent)));
+    assert!(old_parent.is_none());
+    drop(retained);
+}
+
+#[test]
+fn inner_html_needs_an_element() {
+    let mut dom = TestDom::load("<!DOCTYPE html><p>text");
+    let paragraph = &dom.subtree().children[1].children[1].children[0];
+    let text = paragraph.children[0].id;
+    let result = dom.query(|dom, done| call!([dom], set_inner_html(text, "<b>".to_string(), done)));
+    assert_eq!(result, Err(DomError::InvalidNodeType));
+
+    let missing = text + 1000;
+    let result =
+        dom.query(|dom, done| call!([dom], set_inner_html(missing, "<b>".to_string(), done)));
+    assert_eq!(result, Err(DomError::NotFound));
+}
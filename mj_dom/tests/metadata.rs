@@ -0,0 +1,106 @@
+mod common;
+
+use common::TestDom;
+use stakker::call;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head>
+  <title>
+    A   page
+    title </title>
+  <title>Not this one</title>
+  <base target=_blank>
+  <base href="/static/">
+  <base href="https://elsewhere.example/">
+  <meta charset=utf-8>
+  <meta name=Description content="What it is">
+  <meta http-equiv=Refresh content="5; url=/next">
+  <meta name=empty>
+  <link rel="Stylesheet alternate" href="site.css" media=print>
+  <link rel=icon href="//cdn.example/icon.png" type=image/png sizes=32x32>
+  <link rel=preload>
+</head><body></body></html>"#;
+
+const DOCUMENT_URL: &str = "https://example.com/docs/page.html";
+
+#[test]
+fn title_collapses_whitespace() {
+    let mut dom = TestDom::load(PAGE);
+    let title = dom.query(|dom, title| call!([dom], title(title)));
+    assert_eq!(title, "A page title");
+}
+
+#[test]
+fn documents_without_a_title_have_an_empty_one() {
+    let mut dom = TestDom::load("<p>No title here</p>");
+    assert_eq!(dom.query(|dom, title| call!([dom], title(title))), "");
+}
+
+#[test]
+fn svg_documents_take_the_title_under_the_root() {
+    let mut dom = TestDom::new();
+    dom.parse_bytes(
+        br#"<svg xmlns="http://www.w3.org/2000/svg"><title> Drawing </title></svg>"#,
+        Some("image/svg+xml"),
+    );
+    assert_eq!(
+        dom.query(|dom, title| call!([dom], title(title))),
+        " Drawing "
+    );
+}
+
+#[test]
+fn base_url_follows_the_first_base_with_an_href() {
+    let mut dom = TestDom::load_from(DOCUMENT_URL, PAGE);
+    let base = dom.query(|dom, base| call!([dom], base_url(base)));
+    assert_eq!(base.as_str(), "https://example.com/static/");
+}
+
+#[test]
+fn base_url_falls_back_to_the_document_url() {
+    let mut dom = TestDom::load_from(DOCUMENT_URL, "<p>No base here</p>");
+    let base = dom.query(|dom, base| call!([dom], base_url(base)));
+    assert_eq!(base.as_str(), DOCUMENT_URL);
+}
+
+#[test]
+fn meta_needs_content_and_a_key() {
+    let mut dom = TestDom::load(PAGE);
+    let meta = dom.query(|dom, meta| call!([dom], meta(meta)));
+    let pairs = meta
+        .iter()
+        .map(|entry| {
+            (
+                entry.name.as_deref(),
+                entry.http_equiv.as_deref(),
+                entry.content.as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pairs,
+        [
+            (Some("description"), None, "What it is"),
+            (None, Some("refresh"), "5; url=/next"),
+        ]
+    );
+}
+
+#[test]
+fn links_resolve_against_the_base_url() {
+    let mut dom = TestDom::load_from(DOCUMENT_URL, PAGE);
+    let links = dom.query(|dom, links| call!([dom], links(links)));
+    assert_eq!(links.len(), 2, "Links without an href are left out");
+
+    assert!(links[0].has_rel("stylesheet") && links[0].has_rel("alternate"));
+    assert_eq!(
+        links[0].href.as_str(),
+        "https://example.com/static/site.css"
+    );
+    assert_eq!(links[0].media.as_deref(), Some("print"));
+
+    assert!(links[1].has_rel("icon"));
+    assert_eq!(links[1].href.as_str(), "https://cdn.example/icon.png");
+    assert_eq!(links[1].sizes.as_deref(), Some("32x32"));
+    assert_eq!(links[1].mime_type.as_deref(), Some("image/png"));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use common::TestDom;
+use ecow::EcoString;
+use mj_dom::{
+    mutation::{MutationKind, MutationRecord, ObserverId},
+    NodeId,
+};
+use stakker::{call, fwd_do};
+
+const PAGE: &str = "<!DOCTYPE html><div id=watched><p id=inner>text</p></div><div id=other></div>";
+
+type Records = Rc<RefCell<Vec<MutationRecord>>>;
+
+fn observe(dom: &mut TestDom, target: NodeId) -> (ObserverId, Records) {
+    let records = Records::default();
+    let sink = records.clone();
+    let observer = fwd_do!(move |record: MutationRecord| sink.borrow_mut().push(record));
+    let id = dom.query(|dom, id| call!([dom], observe(target, observer, id)));
+    (id, records)
+}
+
+fn set_attribute(dom: &mut TestDom, element: NodeId, name: &str, value: &str) {
+    let (name, value) = (EcoString::from(name), EcoString::from(value));
+    let result = dom.query(|dom, done| call!([dom], set_attribute(element, name, value, done)));
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn attribute_changes_are_recorded_with_their_old_value() {
+    let mut dom = TestDom::load(PAGE);
+    let watched = dom.by_id("watched");
+    let inner = dom.by_id("inner");
+    let (_, records) = observe(&mut dom, watched);
+
+    set_attribute(&mut dom, inner, "title", "first");
+    set_attribute(&mut dom, inner, "id", "renamed");
+    // Setting the value it already has is still a change
+    set_attribute(&mut dom, inner, "title", "first");
+
+    let records = records.borrow();
+    assert_eq!(records.len(), 3);
+    let MutationKind::Attributes { name, old_value } = &records[0].kind else {
+        panic!("Expected an attribute record");
+    };
+    assert_eq!((&*name.local, old_value), ("title", &None));
+    let MutationKind::Attributes { name, old_value } = &records[1].kind else {
+        panic!("Expected an attribute record");
+    };
+    assert_eq!(&*name.local, "id");
+    assert_eq!(old_value.as_deref(), Some("inner"));
+    let MutationKind::Attributes { name, old_value } = &records[2].kind else {
+        panic!("Expected an attribute record");
+    };
+    assert_eq!(&*name.local, "title");
+    assert_eq!(old_value.as_deref(), Some("first"));
+}
+
+#[test]
+fn records_are_limited_to_the_observed_subtree() {
+    let mut dom = TestDom::load(PAGE);
+    let watched = dom.by_id("watched");
+    let other = dom.by_id("other");
+    let (_, records) = observe(&mut dom, watched);
+
+    set_attribute(&mut dom, other, "title", "unseen");
+    assert!(records.borrow().is_empty());
+}
+
+#[test]
+fn inner_html_is_recorded_as_a_child_list_change() {
+    let mut dom = TestDom::load(PAGE);
+    let watched = dom.by_id("watched");
+    let (_, records) = observe(&mut dom, watched);
+
+    let result = dom.query(|dom, done| {
+        call!(
+            [dom],
+            set_inner_html(watched, "<b>one</b><i>two</i>".to_string(), done)
+        )
+    });
+    assert_eq!(result, Ok(()));
+
+    let records = records.borrow();
+    assert_eq!(records.len(), 1);
+    let MutationKind::ChildList { added, removed, .. } = &records[0].kind else {
+        panic!("Expected a child list record");
+    };
+    assert_eq!((added.len(), removed.len()), (2, 1));
+}
+
+#[test]
+fn text_changes_are_character_data_records() {
+    let mut dom = TestDom::load(PAGE);
+    let inner = dom.by_id("inner");
+    let text = dom
+        .query(|dom, child| call!([dom], first_child(inner, child)))
+        .expect("The paragraph has text");
+    let (_, records) = observe(&mut dom, inner);
+
+    call!([dom.dom], append_text_content(text, " more".into()));
+    dom.run_until(|| !records.borrow().is_empty());
+    let MutationKind::CharacterData { old_value } = &records.borrow()[0].kind else {
+        panic!("Expected a character data record");
+    };
+    assert_eq!(old_value, "text");
+}
+
+#[test]
+fn disconnected_observers_hear_nothing_more() {
+    let mut dom = TestDom::load(PAGE);
+    let watched = dom.by_id("watched");
+    let (id, records) = observe(&mut dom, watched);
+
+    set_attribute(&mut dom, watched, "title", "seen");
+    call!([dom.dom], disconnect(id));
+    set_attribute(&mut dom, watched, "title", "unseen");
+    assert_eq!(records.borrow().len(), 1);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::error::DomError;
+use stakker::call;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<head><base href="/assets/"></head>
+<body>
+  <a id=relative href="../about?x=1#top">About</a>
+  <a id=absolute href="  https://other.example/  ">Other</a>
+  <img id=image src="logo.png">
+  <form id=form action="//forms.example/send"></form>
+  <a id=broken href="http://[::1">Broken</a>
+  <a id=missing>Nowhere</a>
+</body>"#;
+
+fn resolve(dom: &mut TestDom, selectors: &str, attribute: &str) -> Option<String> {
+    let node = dom.find(selectors);
+    let attribute = attribute.into();
+    dom.query(|dom, url| call!([dom], resolve_attribute(node, attribute, url)))
+        .expect("Attribute should resolve")
+        .map(String::from)
+}
+
+#[test]
+fn attributes_resolve_against_the_base_url() {
+    let mut dom = TestDom::load_from("https://example.com/docs/page.html", PAGE);
+    assert_eq!(
+        resolve(&mut dom, "#relative", "href").as_deref(),
+        Some("https://example.com/about?x=1#top")
+    );
+    assert_eq!(
+        resolve(&mut dom, "#absolute", "href").as_deref(),
+        Some("https://other.example/")
+    );
+    assert_eq!(
+        resolve(&mut dom, "#image", "src").as_deref(),
+        Some("https://example.com/assets/logo.png")
+    );
+    assert_eq!(
+        resolve(&mut dom, "#form", "action").as_deref(),
+        Some("https://forms.example/send")
+    );
+    assert_eq!(resolve(&mut dom, "#missing", "href"), None);
+}
+
+#[test]
+fn unparseable_urls_are_errors() {
+    let mut dom = TestDom::load_from("https://example.com/", PAGE);
+    let broken = dom.find("#broken");
+    let result = dom.query(|dom, url| call!([dom], resolve_attribute(broken, "href".into(), url)));
+    assert_eq!(result, Err(DomError::Syntax("http://[::1".to_string())));
+}
+
+#[test]
+fn relative_urls_need_a_base_that_can_have_them() {
+    // A document that wasn't fetched from anywhere is at about:blank
+    let mut dom = TestDom::load("<a href=page.html>Page</a>");
+    let result = dom.query(|dom, url| call!([dom], resolve_url("page.html".into(), url)));
+    assert_eq!(result, Err(DomError::Syntax("page.html".to_string())));
+}
+
+#[test]
+fn attributes_resolve_in_batches() {
+    let mut dom = TestDom::load_from("https://example.com/", PAGE);
+    let nodes = ["#relative", "#image", "#missing"].map(|selectors| dom.find(selectors));
+    let resolved = dom.query(|dom, resolved| {
+        call!(
+            [dom],
+            resolve_attributes(nodes.to_vec(), "href".into(), resolved)
+        )
+    });
+    let urls = resolved
+        .into_iter()
+        .map(|(_, url)| url.map(|url| url.map(String::from)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        [
+            Ok(Some("https://example.com/about?x=1#top".to_string())),
+            Ok(None),
+            Ok(None)
+        ]
+    );
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::{error::DomError, NodeId};
+use stakker::call;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<ul id=list>
+  <li id=one class="item first">One</li>
+  <li id=two class=item data-kind=special>Two</li>
+  <li id=three class="item last"><a id=link href="/three">Three</a></li>
+</ul>
+<form id=form>
+  <input id=box type=checkbox checked>
+  <input id=off type=text disabled>
+  <template id=template><p id=hidden class=item></p></template>
+</form>
+<p id=empty></p>"#;
+
+fn select_all(dom: &mut TestDom, scope: Option<NodeId>, selectors: &str) -> Vec<String> {
+    let selectors = selectors.to_string();
+    let found = dom
+        .query(|dom, found| call!([dom], query_selector_all(scope, selectors, found)))
+        .expect("Selector should parse");
+    dom.ids(found)
+}
+
+#[test]
+fn simple_selectors_match_by_type_class_id_and_attribute() {
+    let mut dom = TestDom::load(PAGE);
+    assert_eq!(select_all(&mut dom, None, "li"), ["one", "two", "three"]);
+    assert_eq!(select_all(&mut dom, None, ".item.last"), ["three"]);
+    assert_eq!(select_all(&mut dom, None, "#two"), ["two"]);
+    assert_eq!(select_all(&mut dom, None, "[data-kind=special]"), ["two"]);
+    assert_eq!(select_all(&mut dom, None, "[href^='/th']"), ["link"]);
+    assert_eq!(select_all(&mut dom, None, "LI#ONE"), Vec::<String>::new());
+}
+
+#[test]
+fn combinators_and_structural_pseudo_classes() {
+    let mut dom = TestDom::load(PAGE);
+    assert_eq!(select_all(&mut dom, None, "ul > li > a"), ["link"]);
+    assert_eq!(select_all(&mut dom, None, "#one + li"), ["two"]);
+    assert_eq!(select_all(&mut dom, None, "#one ~ li"), ["two", "three"]);
+    assert_eq!(
+        select_all(&mut dom, None, "li:nth-child(2n+1)"),
+        ["one", "three"]
+    );
+    assert_eq!(select_all(&mut dom, None, "li:not(.first, .last)"), ["two"]);
+    assert_eq!(select_all(&mut dom, None, "li:last-child a"), ["link"]);
+    assert_eq!(select_all(&mut dom, None, "p:empty"), ["empty"]);
+    assert_eq!(select_all(&mut dom, None, ":root"), [""]);
+}
+
+#[test]
+fn state_pseudo_classes() {
+    let mut dom = TestDom::load(PAGE);
+    assert_eq!(select_all(&mut dom, None, ":checked"), ["box"]);
+    assert_eq!(select_all(&mut dom, None, "input:disabled"), ["off"]);
+    assert_eq!(select_all(&mut dom, None, "input:enabled"), ["box"]);
+    assert_eq!(select_all(&mut dom, None, "a:link"), ["link"]);
+    assert_eq!(select_all(&mut dom, None, ":hover"), Vec::<String>::new());
+}
+
+#[test]
+fn results_come_in_tree_order_without_template_contents() {
+    let mut dom = TestDom::load(PAGE);
+    assert_eq!(
+        select_all(&mut dom, None, "#three, .item, #list"),
+        ["list", "one", "two", "three"]
+    );
+    let first = dom.find("li, ul");
+    assert_eq!(dom.ids(vec![first]), ["list"]);
+}
+
+#[test]
+fn scoped_queries_only_return_descendants() {
+    let mut dom = TestDom::load(PAGE);
+    let list = dom.find("#list");
+    // Ancestors outside the scope still count towards a match
+    assert_eq!(select_all(&mut dom, Some(list), "body li.last"), ["three"]);
+    assert_eq!(select_all(&mut dom, Some(list), ":scope > .first"), ["one"]);
+    assert_eq!(select_all(&mut dom, Some(list), "ul"), Vec::<String>::new());
+
+    let template = dom.find("template");
+    let contents = dom
+        .query(|dom, contents| call!([dom], template_contents(template, contents)))
+        .expect("Templates have contents");
+    assert_eq!(select_all(&mut dom, Some(contents), ".item"), ["hidden"]);
+}
+
+#[test]
+fn matches_tests_a_single_element() {
+    let mut dom = TestDom::load(PAGE);
+    let two = dom.find("#two");
+    let check = |dom: &mut TestDom, selectors: &str| {
+        let (element, selectors) = (two, selectors.to_string());
+        dom.query(|dom, result| call!([dom], matches(element, selectors, result)))
+    };
+    assert_eq!(check(&mut dom, "ul > li:nth-of-type(2)"), Ok(true));
+    assert_eq!(check(&mut dom, ".first"), Ok(false));
+}
+
+#[test]
+fn invalid_selectors_are_syntax_errors() {
+    let mut dom = TestDom::load(PAGE);
+    let result =
+        dom.query(|dom, found| call!([dom], query_selector_all(None, "li >".to_string(), found)));
+    assert_eq!(
+        result.map(|found| found.len()),
+        Err(DomError::Syntax("li >".to_string()))
+    );
+    let result =
+        dom.query(|dom, found| call!([dom], query_selector(None, "::before".to_string(), found)));
+    assert!(result.is_err());
+}
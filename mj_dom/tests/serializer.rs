@@ -0,0 +1,90 @@
+mod common;
+
+use common::TestDom;
+use stakker::call;
+
+fn document_html(html: &str) -> String {
+    TestDom::load(html).query(|dom, html| call!([dom], document_html(html)))
+}
+
+#[test]
+fn documents_serialize_back_to_their_markup() {
+    let html = r#"<!DOCTYPE html><html><head><title>Page</title></head><body><p class="intro">Hello <b>world</b></p><!-- note --></body></html>"#;
+    assert_eq!(document_html(html), html);
+}
+
+#[test]
+fn void_elements_have_no_end_tag() {
+    assert_eq!(
+        document_html("<!DOCTYPE html><p>a<br>b<img src=x.png></p>"),
+        r#"<!DOCTYPE html><html><head></head><body><p>a<br>b<img src="x.png"></p></body></html>"#
+    );
+}
+
+#[test]
+fn text_and_attributes_are_escaped() {
+    assert_eq!(
+        document_html(
+            "<!DOCTYPE html><p title='&quot;1 < 2&quot; &amp;'>1 &lt; 2 &amp;&nbsp;3</p>"
+        ),
+        "<!DOCTYPE html><html><head></head><body>\
+         <p title=\"&quot;1 &lt; 2&quot; &amp;\">1 &lt; 2 &amp;&nbsp;3</p></body></html>"
+    );
+}
+
+#[test]
+fn raw_text_is_written_as_is() {
+    assert_eq!(
+        document_html("<!DOCTYPE html><script>if (a < b && c) {}</script><style>a > b {}</style>"),
+        "<!DOCTYPE html><html><head><script>if (a < b && c) {}</script>\
+         <style>a > b {}</style></head><body></body></html>"
+    );
+}
+
+#[test]
+fn foreign_elements_keep_their_names() {
+    assert_eq!(
+        document_html(r##"<!DOCTYPE html><svg viewBox="0 0 1 1"><use xlink:href="#a"/></svg>"##),
+        r##"<!DOCTYPE html><html><head></head><body><svg viewBox="0 0 1 1"><use xlink:href="#a"></use></svg></body></html>"##
+    );
+}
+
+#[test]
+fn inner_and_outer_html_of_an_element() {
+    let mut dom = TestDom::load(
+        "<!DOCTYPE html><template id=t><p>inside</p></template><div id=d><i>x</i></div>",
+    );
+    let tree = dom.subtree();
+    let head = &tree.children[1].children[0];
+    let template = head.children[0].id;
+    let div = tree.children[1].children[1].children[0].id;
+
+    let outer = dom.query(|dom, html| call!([dom], outer_html(div, html)));
+    assert_eq!(outer, r#"<div id="d"><i>x</i></div>"#);
+    let inner = dom.query(|dom, html| call!([dom], inner_html(div, html)));
+    assert_eq!(inner, "<i>x</i>");
+
+    let outer = dom.query(|dom, html| call!([dom], outer_html(template, html)));
+    assert_eq!(outer, r#"<template id="t"><p>inside</p></template>"#);
+    let inner = dom.query(|dom, html| call!([dom], inner_html(template, html)));
+    assert_eq!(inner, "<p>inside</p>");
+}
+
+#[test]
+fn attributes_keep_their_order() {
+    let mut dom = TestDom::load("<!DOCTYPE html><p id=p z=1 a=2 m=3></p>");
+    let p = dom.by_id("p");
+    let outer = dom.query(|dom, html| call!([dom], outer_html(p, html)));
+    assert_eq!(outer, r#"<p id="p" z="1" a="2" m="3"></p>"#);
+
+    // Changing a value keeps its place, removing one closes the gap, and new ones go last
+    let result =
+        dom.query(|dom, done| call!([dom], set_attribute(p, "a".into(), "4".into(), done)));
+    assert_eq!(result, Ok(()));
+    call!([dom.dom], remove_attribute(p, "z".into()));
+    let result =
+        dom.query(|dom, done| call!([dom], set_attribute(p, "b".into(), "5".into(), done)));
+    assert_eq!(result, Ok(()));
+    let outer = dom.query(|dom, html| call!([dom], outer_html(p, html)));
+    assert_eq!(outer, r#"<p id="p" a="4" m="3" b="5"></p>"#);
+}
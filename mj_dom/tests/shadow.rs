@@ -0,0 +1,158 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::{
+    error::DomError,
+    shadow::{ShadowRootInit, ShadowRootMode},
+    NodeId,
+};
+use stakker::call;
+
+const PAGE: &str = r#"<!DOCTYPE html><body>
+<div id=host><template shadowrootmode=open shadowrootdelegatesfocus><p id=inner><slot name=title id=named></slot><slot id=default><i id=fallback>Nothing</i></slot><slot id=unused><b id=kept>Kept</b></slot></p></template><h1 slot=title id=title>Title</h1>Text<template shadowrootmode=open id=second></template></div>
+<span id=closed><template shadowrootmode=closed><em>Hidden</em></template></span>
+<a id=anchor><template shadowrootmode=open id=plain></template></a>
+<span id=empty></span>
+</body>"#;
+
+fn children(dom: &mut TestDom, node: NodeId) -> Vec<NodeId> {
+    let mut children = vec![];
+    let mut next = dom.query(|dom, child| call!([dom], first_child(node, child)));
+    while let Some(child) = next {
+        children.push(child);
+        next = dom.query(|dom, sibling| call!([dom], next_sibling(child, sibling)));
+    }
+    children
+}
+
+fn shadow_root(dom: &mut TestDom, host: NodeId) -> Option<NodeId> {
+    dom.query(|dom, root| call!([dom], shadow_root(host, root)))
+}
+
+#[test]
+fn declarative_templates_become_shadow_roots() {
+    let mut dom = TestDom::load(PAGE);
+    let host = dom.find("#host");
+    let root = shadow_root(&mut dom, host).expect("The host should have an open shadow root");
+    let shadow = dom
+        .query(|dom, shadow| call!([dom], shadow(root, shadow)))
+        .expect("The root should be a shadow root");
+    assert_eq!(shadow.host, host);
+    assert_eq!(shadow.init.mode, ShadowRootMode::Open);
+    assert!(shadow.init.delegates_focus && !shadow.init.clonable);
+    assert!(shadow.declarative);
+
+    // The first template is gone, while the second is left as it is as the host already has one
+    let children = children(&mut dom, host);
+    assert_eq!(children.len(), 3);
+    assert_eq!(children[0], dom.find("#title"));
+    assert_eq!(children[2], dom.find("#second"));
+
+    // The shadow tree isn't part of the document, so it can't be found from there
+    let found = dom.query(|dom, found| call!([dom], query_selector(None, "#inner".into(), found)));
+    assert_eq!(found, Ok(None));
+    dom.find_in(root, "#inner");
+}
+
+#[test]
+fn only_valid_hosts_take_declarative_shadow_roots() {
+    let mut dom = TestDom::load(PAGE);
+    let closed = dom.find("#closed");
+    assert_eq!(
+        shadow_root(&mut dom, closed),
+        None,
+        "Closed roots are hidden"
+    );
+    assert!(children(&mut dom, closed).is_empty());
+
+    let anchor = dom.find("#anchor");
+    assert_eq!(shadow_root(&mut dom, anchor), None);
+    assert_eq!(children(&mut dom, anchor), [dom.find("#plain")]);
+}
+
+#[test]
+fn inner_html_does_not_declare_shadow_roots() {
+    let mut dom = TestDom::load(PAGE);
+    let empty = dom.find("#empty");
+    let html = "<template shadowrootmode=open></template>".to_string();
+    let result = dom.query(|dom, done| call!([dom], set_inner_html(empty, html, done)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(shadow_root(&mut dom, empty), None);
+    assert_eq!(children(&mut dom, empty).len(), 1);
+}
+
+#[test]
+fn slots_take_the_host_children_asking_for_them() {
+    let mut dom = TestDom::load(PAGE);
+    let (host, title) = (dom.find("#host"), dom.find("#title"));
+    let text = children(&mut dom, host)[1];
+    let root = shadow_root(&mut dom, host).unwrap();
+    let (named, default, unused) = (
+        dom.find_in(root, "#named"),
+        dom.find_in(root, "#default"),
+        dom.find_in(root, "#unused"),
+    );
+
+    let assigned = dom.query(|dom, assigned| call!([dom], assigned_nodes(named, assigned)));
+    assert_eq!(assigned, [title]);
+    let assigned = dom.query(|dom, slot| call!([dom], assigned_slot(text, slot)));
+    assert_eq!(assigned, Some(default), "Text goes to the default slot");
+    let second = dom.find("#second");
+    let assigned = dom.query(|dom, slot| call!([dom], assigned_slot(second, slot)));
+    assert_eq!(assigned, Some(default));
+
+    // The flat tree shows the shadow tree in place of the host's children
+    let flat = dom.query(|dom, flat| call!([dom], flat_children(host, flat)));
+    assert_eq!(flat, [dom.find_in(root, "#inner")]);
+    let flat = dom.query(|dom, flat| call!([dom], flat_children(default, flat)));
+    assert_eq!(flat, [text, second]);
+    let flat = dom.query(|dom, flat| call!([dom], flat_children(unused, flat)));
+    assert_eq!(
+        flat,
+        [dom.find_in(root, "#kept")],
+        "Unassigned slots show their fallback"
+    );
+}
+
+#[test]
+fn shadow_roots_can_be_attached_to_valid_hosts_once() {
+    let mut dom = TestDom::load(PAGE);
+    let empty = dom.find("#empty");
+    let init = ShadowRootInit::new(ShadowRootMode::Open);
+    let root = dom
+        .query(|dom, root| call!([dom], attach_shadow(empty, init, root)))
+        .expect("A span can host a shadow root");
+    assert_eq!(shadow_root(&mut dom, empty), Some(root));
+
+    let again = dom.query(|dom, root| call!([dom], attach_shadow(empty, init, root)));
+    assert_eq!(again, Err(DomError::NotSupported));
+    let anchor = dom.find("#anchor");
+    let result = dom.query(|dom, root| call!([dom], attach_shadow(anchor, init, root)));
+    assert_eq!(result, Err(DomError::NotSupported));
+}
+
+#[test]
+fn attaching_over_a_declarative_root_empties_it() {
+    let mut dom = TestDom::load(PAGE);
+    let host = dom.find("#host");
+    let declared = shadow_root(&mut dom, host).unwrap();
+    let init = ShadowRootInit::new(ShadowRootMode::Open);
+    let root = dom.query(|dom, root| call!([dom], attach_shadow(host, init, root)));
+    assert_eq!(root, Ok(declared));
+    assert!(children(&mut dom, declared).is_empty());
+
+    let closed = ShadowRootInit::new(ShadowRootMode::Closed);
+    let result = dom.query(|dom, root| call!([dom], attach_shadow(host, closed, root)));
+    assert_eq!(result, Err(DomError::NotSupported));
+}
+
+#[test]
+fn shadow_trees_are_released_with_their_host() {
+    let mut dom = TestDom::load(PAGE);
+    let host = dom.find("#host");
+    let root = shadow_root(&mut dom, host).unwrap();
+    let result = dom.query(|dom, done| call!([dom], remove(host, done)));
+    assert_eq!(result, Ok(()));
+    let alive = dom.query(|dom, alive| call!([dom], contains(root, alive)));
+    assert!(!alive);
+}
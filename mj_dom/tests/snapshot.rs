@@ -0,0 +1,81 @@
+mod common;
+
+use std::{sync::Arc, thread};
+
+use common::TestDom;
+use mj_dom::{snapshot::DomSnapshot, DOCUMENT_NODE};
+use stakker::call;
+
+const PAGE: &str = "<!DOCTYPE html><div id=list><p id=a>one</p><p id=b>two</p></div>";
+
+fn snapshot(dom: &mut TestDom) -> Arc<DomSnapshot> {
+    dom.query(|dom, snapshot| call!([dom], snapshot(snapshot)))
+}
+
+#[test]
+fn snapshots_can_be_read_on_another_thread() {
+    let mut dom = TestDom::load(PAGE);
+    let list = dom.by_id("list");
+    let snapshot = snapshot(&mut dom);
+
+    let (text, ids) = thread::spawn(move || {
+        let ids = snapshot
+            .children(list)
+            .filter_map(|child| snapshot.attribute(child, "id"))
+            .collect::<Vec<_>>();
+        (snapshot.text_content(DOCUMENT_NODE), ids)
+    })
+    .join()
+    .unwrap();
+    assert_eq!(text, "onetwo");
+    assert_eq!(ids, ["a", "b"]);
+}
+
+#[test]
+fn snapshots_stay_as_they_were_while_the_tree_changes() {
+    let mut dom = TestDom::load(PAGE);
+    let (list, a, b) = (dom.by_id("list"), dom.by_id("a"), dom.by_id("b"));
+    let before = snapshot(&mut dom);
+    assert!(
+        Arc::ptr_eq(&before, &snapshot(&mut dom)),
+        "Nothing changed, so the snapshot is reused"
+    );
+
+    let result = dom.query(|dom, done| call!([dom], remove_child(list, b, done)));
+    assert_eq!(result, Ok(()));
+    let after = snapshot(&mut dom);
+
+    assert!(after.version() > before.version());
+    assert_eq!(before.children(list).count(), 2);
+    assert_eq!(after.children(list).count(), 1);
+    assert!(before.contains(b) && !after.contains(b));
+    assert_eq!(after.text_content(a), "one");
+    let text = after.get(a).unwrap().first_child.unwrap();
+    assert!(
+        std::ptr::eq(before.get(text).unwrap(), after.get(text).unwrap()),
+        "Unchanged nodes are shared between snapshots"
+    );
+}
+
+#[test]
+fn snapshots_reach_shadow_trees_through_their_host() {
+    let mut dom = TestDom::load(
+        "<div id=host><template shadowrootmode=closed><b>shadow</b></template>light</div>",
+    );
+    let host = dom.by_id("host");
+    let snapshot = snapshot(&mut dom);
+
+    let root = snapshot
+        .get(host)
+        .unwrap()
+        .shadow_root
+        .expect("Closed roots are kept too");
+    let shadow = snapshot
+        .get(root)
+        .unwrap()
+        .shadow
+        .expect("The root knows its host");
+    assert_eq!(shadow.host, host);
+    assert_eq!(snapshot.text_content(root), "shadow");
+    assert_eq!(snapshot.text_content(host), "light");
+}
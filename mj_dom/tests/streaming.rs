@@ -0,0 +1,119 @@
+mod common;
+
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::TestDom;
+use encoding_rs::{UTF_8, WINDOWS_1252};
+use mj_dom::{
+    nodes::{DomSubtree, MemberKind},
+    parser::DocumentChunk,
+};
+use stakker::{call, ret_some_do};
+
+fn start(dom: &mut TestDom, content_type: Option<&str>) {
+    let content_type = content_type.map(str::to_string);
+    call!(
+        [dom.dom],
+        stream_document(DocumentChunk::Start {
+            content_type,
+            url: None
+        })
+    );
+}
+
+fn send(dom: &mut TestDom, bytes: &[u8]) {
+    call!(
+        [dom.dom],
+        stream_document(DocumentChunk::Data(bytes.to_vec()))
+    );
+}
+
+fn finish(dom: &mut TestDom) {
+    call!([dom.dom], stream_document(DocumentChunk::End));
+    dom.query(|dom, loaded| call!([dom], when_loaded(loaded)))
+}
+
+fn text(subtree: &DomSubtree) -> String {
+    match &subtree.kind {
+        MemberKind::Text { contents } => contents.to_string(),
+        _ => subtree.children.iter().map(text).collect(),
+    }
+}
+
+#[test]
+fn chunks_split_inside_a_character_are_decoded_whole() {
+    let html = "<!DOCTYPE html><p>Grüße, 世界</p>".as_bytes();
+    let mut dom = TestDom::new();
+    start(&mut dom, Some("text/html; charset=utf-8"));
+    for byte in html.chunks(1) {
+        send(&mut dom, byte);
+    }
+    finish(&mut dom);
+    assert_eq!(text(&dom.subtree()), "Grüße, 世界");
+}
+
+#[test]
+fn meta_charset_is_found_across_small_chunks() {
+    let html = b"<meta charset=windows-1252><p>Caf\xE9";
+    let mut dom = TestDom::new();
+    start(&mut dom, None);
+    for chunk in html.chunks(4) {
+        send(&mut dom, chunk);
+    }
+    finish(&mut dom);
+    assert_eq!(text(&dom.subtree()), "Café");
+    assert_eq!(
+        dom.query(|dom, encoding| call!([dom], encoding(encoding))),
+        WINDOWS_1252
+    );
+}
+
+#[test]
+fn document_grows_before_the_stream_ends() {
+    let mut dom = TestDom::new();
+    start(&mut dom, Some("text/html; charset=utf-8"));
+    let loaded = Rc::new(Cell::new(false));
+    let flag = loaded.clone();
+    call!(
+        [dom.dom],
+        when_loaded(ret_some_do!(move |()| flag.set(true)))
+    );
+
+    // Past the prescan, so the parser has no reason to wait for more
+    let head = format!(
+        "<!DOCTYPE html><title>{}</title><p>first</p>",
+        "x".repeat(2048)
+    );
+    send(&mut dom, head.as_bytes());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !text(&dom.subtree()).ends_with("first") {
+        assert!(Instant::now() < deadline, "The paragraph never arrived");
+    }
+    assert!(!loaded.get());
+
+    send(&mut dom, b"<p>second");
+    finish(&mut dom);
+    assert!(loaded.get());
+    assert!(text(&dom.subtree()).ends_with("firstsecond"));
+    assert_eq!(
+        dom.query(|dom, encoding| call!([dom], encoding(encoding))),
+        UTF_8
+    );
+}
+
+#[test]
+fn a_new_document_abandons_one_still_streaming() {
+    let mut dom = TestDom::new();
+    start(&mut dom, Some("text/html; charset=utf-8"));
+    let abandoned = format!("<!DOCTYPE html><p>{}", "<b>old</b>".repeat(1000));
+    send(&mut dom, abandoned.as_bytes());
+
+    start(&mut dom, Some("text/html; charset=utf-8"));
+    send(&mut dom, b"<!DOCTYPE html><p>new");
+    finish(&mut dom);
+    assert_eq!(text(&dom.subtree()), "new");
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use common::TestDom;
+use mj_dom::{error::DomError, NodeId};
+use stakker::{call, ret_nop};
+
+const PAGE: &str =
+    "<!DOCTYPE html><div id=list><p id=a></p><p id=b></p><p id=c></p></div><div id=other></div>";
+
+fn inner_html(dom: &mut TestDom, node: NodeId) -> String {
+    dom.query(|dom, html| call!([dom], inner_html(node, html)))
+}
+
+/// Whether `node` is still in the tree, rather than having been released.
+fn is_alive(dom: &mut TestDom, node: NodeId) -> bool {
+    dom.query(|dom, alive| call!([dom], contains(node, alive)))
+}
+
+#[test]
+fn removed_children_leave_no_gap_and_are_released() {
+    let mut dom = TestDom::load(PAGE);
+    let (list, b) = (dom.by_id("list"), dom.by_id("b"));
+
+    let result = dom.query(|dom, done| call!([dom], remove_child(list, b, done)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        inner_html(&mut dom, list),
+        r#"<p id="a"></p><p id="c"></p>"#
+    );
+    assert!(!is_alive(&mut dom, b));
+    assert!(is_alive(&mut dom, list));
+}
+
+#[test]
+fn only_children_can_be_removed() {
+    let mut dom = TestDom::load(PAGE);
+    let (other, a) = (dom.by_id("other"), dom.by_id("a"));
+
+    let result = dom.query(|dom, done| call!([dom], remove_child(other, a, done)));
+    assert_eq!(result, Err(DomError::NotFound));
+    assert!(is_alive(&mut dom, a));
+}
+
+#[test]
+fn inserting_moves_entries_within_and_between_parents() {
+    let mut dom = TestDom::load(PAGE);
+    let (list, other) = (dom.by_id("list"), dom.by_id("other"));
+    let (a, c) = (dom.by_id("a"), dom.by_id("c"));
+
+    let result = dom.query(|dom, done| call!([dom], insert_before(list, c, Some(a), done)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        inner_html(&mut dom, list),
+        r#"<p id="c"></p><p id="a"></p><p id="b"></p>"#
+    );
+
+    let result = dom.query(|dom, done| call!([dom], append(other, a, done)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        inner_html(&mut dom, list),
+        r#"<p id="c"></p><p id="b"></p>"#
+    );
+    assert_eq!(inner_html(&mut dom, other), r#"<p id="a"></p>"#);
+    assert!(is_alive(&mut dom, a), "Moved entries are not released");
+
+    let parent = dom.query(|dom, parent| call!([dom], parent(a, parent)));
+    assert_eq!(parent, Some(other));
+}
+
+#[test]
+fn entries_cannot_go_inside_themselves_or_text() {
+    let mut dom = TestDom::load("<!DOCTYPE html><div id=list>text<p id=a></p></div>");
+    let (list, a) = (dom.by_id("list"), dom.by_id("a"));
+
+    let result = dom.query(|dom, done| call!([dom], append(a, list, done)));
+    assert_eq!(result, Err(DomError::HierarchyRequest));
+
+    let text = dom
+        .query(|dom, child| call!([dom], first_child(list, child)))
+        .expect("The list starts with text");
+    let result = dom.query(|dom, done| call!([dom], append(text, a, done)));
+    assert_eq!(result, Err(DomError::HierarchyRequest));
+    assert_eq!(inner_html(&mut dom, list), r#"text<p id="a"></p>"#);
+}
+
+#[test]
+fn replacing_puts_the_new_child_in_place_of_the_old() {
+    let mut dom = TestDom::load(PAGE);
+    let list = dom.by_id("list");
+    let (a, b, other) = (dom.by_id("a"), dom.by_id("b"), dom.by_id("other"));
+
+    let result = dom.query(|dom, done| call!([dom], replace_child(list, other, b, done)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        inner_html(&mut dom, list),
+        r#"<p id="a"></p><div id="other"></div><p id="c"></p>"#
+    );
+    assert!(!is_alive(&mut dom, b));
+
+    let result = dom.query(|dom, done| call!([dom], replace_child(list, a, b, done)));
+    assert_eq!(result, Err(DomError::NotFound));
+}
+
+#[test]
+fn retained_entries_survive_detachment_with_their_tree() {
+    let mut dom = TestDom::load(PAGE);
+    let (list, other) = (dom.by_id("list"), dom.by_id("other"));
+    let a = dom.by_id("a");
+    let retained = dom.query(|dom, retained| call!([dom], retain(a, retained)));
+
+    let result = dom.query(|dom, done| call!([dom], remove(list, done)));
+    assert_eq!(result, Ok(()));
+    assert!(
+        is_alive(&mut dom, list),
+        "The retained child keeps its tree"
+    );
+    assert!(dom.element_by_id("b").is_none());
+
+    let result = dom.query(|dom, done| call!([dom], append(other, list, done)));
+    assert_eq!(result, Ok(()));
+    drop(retained);
+    let b = dom.by_id("b");
+    assert!(
+        is_alive(&mut dom, b),
+        "Back in the document, nothing is released"
+    );
+
+    let result = dom.query(|dom, done| call!([dom], remove(other, done)));
+    assert_eq!(result, Ok(()));
+    assert!(!is_alive(&mut dom, b));
+}
+
+#[test]
+fn inner_html_of_an_element_released_meanwhile_is_not_found() {
+    let mut dom = TestDom::load(PAGE);
+    let (list, a) = (dom.by_id("list"), dom.by_id("a"));
+
+    // The element is released at the end of this turn, before the parser has answered
+    let result = dom.query(|dom, done| {
+        call!([dom], set_inner_html(a, "<b>late</b>".to_string(), done));
+        call!([dom], remove(a, ret_nop!()));
+    });
+    assert_eq!(result, Err(DomError::NotFound));
+    assert!(!is_alive(&mut dom, a));
+    assert_eq!(
+        inner_html(&mut dom, list),
+        r#"<p id="b"></p><p id="c"></p>"#
+    );
+}
+
+#[test]
+fn text_content_gathers_the_text_underneath() {
+    let mut dom = TestDom::load("<div id=box>one <b>two</b><!-- not text --> three</div>");
+    let target = dom.by_id("box");
+    let text = dom.query(|dom, text| call!([dom], text_content(target, text)));
+    assert_eq!(text, "one two three");
+
+    // Only text can be appended to, so the element is left alone
+    call!([dom.dom], append_text_content(target, " four".into()));
+    let text = dom.query(|dom, text| call!([dom], text_content(target, text)));
+    assert_eq!(text, "one two three");
+}
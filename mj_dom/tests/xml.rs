@@ -0,0 +1,93 @@
+mod common;
+
+use common::TestDom;
+use html5ever::{namespace_url, ns};
+use mj_dom::{
+    nodes::MemberKind,
+    shadow::{ShadowRootInit, ShadowRootMode},
+    DOCUMENT_NODE,
+};
+use stakker::call;
+
+fn load_xml(source: &str, content_type: &str) -> TestDom {
+    let mut dom = TestDom::new();
+    dom.parse_bytes(source.as_bytes(), Some(content_type));
+    dom
+}
+
+fn text_of(dom: &mut TestDom, selectors: &str) -> String {
+    let node = dom.find(selectors);
+    let subtree = dom.query(|dom, subtree| call!([dom], subtree(node, subtree)));
+    subtree
+        .children
+        .iter()
+        .filter_map(|child| child.kind.text_contents())
+        .map(|text| text.to_string())
+        .collect()
+}
+
+#[test]
+fn xhtml_is_parsed_as_xml() {
+    let mut dom = load_xml(
+        r#"<?xml version="1.0"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><body><p id="a">One<br/>two</p><unknown/></body></html>"#,
+        "application/xhtml+xml",
+    );
+    assert!(dom.query(|dom, xml| call!([dom], is_xml_document(xml))));
+    let diagnostics = dom.query(|dom, diagnostics| call!([dom], diagnostics(diagnostics)));
+    assert_eq!(diagnostics, vec![]);
+    // Self-closing tags close, where the HTML parser would have put the rest inside <unknown>
+    assert_eq!(text_of(&mut dom, "p"), "Onetwo");
+    assert_eq!(
+        dom.query(|dom, html| call!([dom], inner_html(DOCUMENT_NODE, html))),
+        r#"<html><body><p id="a">One<br>two</p><unknown></unknown></body></html>"#
+    );
+}
+
+#[test]
+fn svg_elements_keep_their_namespace() {
+    let mut dom = load_xml(
+        r#"<svg xmlns="http://www.w3.org/2000/svg"><circle r="1"/></svg>"#,
+        "image/svg+xml",
+    );
+    let tree = dom.subtree();
+    let svg = &tree.children[0];
+    let MemberKind::Element { name, .. } = &svg.kind else {
+        panic!("Expected the svg element");
+    };
+    assert_eq!((name.ns.clone(), &*name.local), (ns!(svg), "svg"));
+    assert_eq!(svg.children.len(), 1);
+}
+
+#[test]
+fn malformed_xml_becomes_an_error_document() {
+    let mut dom = load_xml("<root>\n<a>\n</b>\n</root>", "text/xml");
+    let diagnostics = dom.query(|dom, diagnostics| call!([dom], diagnostics(diagnostics)));
+    assert!(!diagnostics.is_empty());
+    let message = text_of(&mut dom, "parsererror");
+    assert!(
+        message.starts_with("XML parsing error on line 3"),
+        "{}",
+        message
+    );
+}
+
+#[test]
+fn html_content_types_still_use_the_html_parser() {
+    let mut dom = load_xml("<p>One<br/>two", "text/html");
+    assert!(!dom.query(|dom, xml| call!([dom], is_xml_document(xml))));
+    assert_eq!(text_of(&mut dom, "p"), "Onetwo");
+}
+
+#[test]
+fn error_documents_take_their_ids_from_the_parser() {
+    let mut dom = load_xml("<root></b>", "text/xml");
+    let body = dom.find("body");
+    // A new node gets an id nothing in the error document has
+    let init = ShadowRootInit::new(ShadowRootMode::Open);
+    let root = dom
+        .query(|dom, root| call!([dom], attach_shadow(body, init, root)))
+        .expect("The body can have a shadow root");
+    assert_ne!(root, body);
+    assert!(text_of(&mut dom, "parsererror").starts_with("XML parsing error on line 1"));
+}
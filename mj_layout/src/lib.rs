@@ -1,4 +1,4 @@
-use mj_dom::{dom_iterator::ForwardDomIterator, nodes::DomEntry, MjDom};
+use mj_dom::{dom_iterator::ForwardDomIterator, MjDom, NodeId};
 use mj_utilities::actor_iterator::ActorIterator;
 use stakker::{call, ret_do, ret_nop, ret_some_do, ret_some_to, Actor, ActorOwn, CX};
 
@@ -27,7 +27,7 @@ impl MjLayout {
             [cx],
             rebuild_layout_tree(None) as (ActorOwn<ForwardDomIterator>)
         );
-        call!([self.dom], iter(callback));
+        call!([self.dom], flat_iter(callback));
     }
 }
 
@@ -35,14 +35,14 @@ impl MjLayout {
     fn rebuild_layout_tree(
         &mut self,
         cx: CX![],
-        node: Option<Actor<DomEntry>>,
+        node: Option<NodeId>,
         iterator: ActorOwn<ForwardDomIterator>,
     ) {
         let this = cx.this().clone();
+        let dom = self.dom.clone();
         let inner_iter = iterator.owned();
-        let callback = ret_some_do!(move |node: Actor<DomEntry>| {
-            let d = node.clone();
-            call!([d], debug());
+        let callback = ret_some_do!(move |node: NodeId| {
+            call!([dom], debug(node));
             call!([this], rebuild_layout_tree(Some(node), inner_iter))
         });
         call!([iterator], next(callback));
@@ -1,3 +1 @@
 pub mod actor_iterator;
-pub mod actor_own_map;
-pub mod macros;